@@ -0,0 +1,261 @@
+use crate::board::*;
+use crate::game::Game;
+use crate::piece::Piece;
+use crate::util::Direction;
+use dirs::home_dir;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Weights of the board features the bot uses to judge a placement. Higher evaluations are better, so
+/// features that describe an unhealthy board (holes, height, ...) should end up with negative weights.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct Weights {
+    pub height: f64,
+    pub lines: f64,
+    pub holes: f64,
+    pub bumpiness: f64,
+    pub wells: f64,
+    pub row_transitions: f64,
+    pub column_transitions: f64,
+}
+
+pub const WEIGHT_COUNT: usize = 7;
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights {
+            height: -0.51,
+            lines: 0.76,
+            holes: -0.36,
+            bumpiness: -0.18,
+            wells: -0.05,
+            row_transitions: -0.05,
+            column_transitions: -0.05,
+        }
+    }
+}
+
+impl Weights {
+    pub fn to_array(self) -> [f64; WEIGHT_COUNT] {
+        [
+            self.height,
+            self.lines,
+            self.holes,
+            self.bumpiness,
+            self.wells,
+            self.row_transitions,
+            self.column_transitions,
+        ]
+    }
+
+    pub fn from_array(a: [f64; WEIGHT_COUNT]) -> Weights {
+        Weights {
+            height: a[0],
+            lines: a[1],
+            holes: a[2],
+            bumpiness: a[3],
+            wells: a[4],
+            row_transitions: a[5],
+            column_transitions: a[6],
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Weights> {
+        let file = File::open(path)?;
+        serde_json::from_reader(file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let ser = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, ser)
+    }
+}
+
+/// Default location of the tuned weights, next to the highscore file.
+pub fn weights_path() -> PathBuf {
+    let mut path = home_dir().unwrap();
+    path.push(".tetris_weights");
+    path
+}
+
+/// A final resting place for a piece: the piece in its chosen orientation and its dropped origin.
+pub struct Placement {
+    pub piece: Piece,
+    pub position: Point,
+    pub evaluation: f64,
+}
+
+pub struct Bot {
+    pub weights: Weights,
+}
+
+impl Bot {
+    pub fn new(weights: Weights) -> Bot {
+        Bot { weights }
+    }
+
+    /// Creates a bot using the tuned weights from `weights_path`, falling back to the defaults when no
+    /// tuned weights exist yet.
+    pub fn load() -> Bot {
+        Bot::new(Weights::load(&weights_path()).unwrap_or_default())
+    }
+
+    /// Tries every orientation and column for `piece`, starting from `spawn`, and returns the placement
+    /// the weights rate highest. Returns None if the piece cannot be placed anywhere.
+    pub fn best_placement(&self, board: &Board, piece: &Piece, spawn: Point) -> Option<Placement> {
+        let mut best: Option<Placement> = None;
+        let mut rotated = piece.clone();
+
+        for _ in 0..4 {
+            let size = rotated.shape.len() as i32;
+            for x in -size..(BOARD_WIDTH as i32) {
+                let mut position = Point { x, y: spawn.y };
+                if board.collision_test(&rotated, position) {
+                    continue;
+                }
+                while !board.collision_test(
+                    &rotated,
+                    Point {
+                        x,
+                        y: position.y + 1,
+                    },
+                ) {
+                    position.y += 1;
+                }
+
+                let mut result = board.clone();
+                result.lock_piece(&rotated, position);
+                let cleared = result.clear_lines();
+                let evaluation = self.evaluate(&result, cleared);
+
+                if best.as_ref().is_none_or(|b| evaluation > b.evaluation) {
+                    best = Some(Placement {
+                        piece: rotated.clone(),
+                        position,
+                        evaluation,
+                    });
+                }
+            }
+            rotated.rotate(Direction::Right);
+        }
+
+        best
+    }
+
+    /// Rates a board after a piece has been locked and `cleared` lines have been removed.
+    pub fn evaluate(&self, board: &Board, cleared: u32) -> f64 {
+        let w = &self.weights;
+        let heights = column_heights(board);
+
+        let height: u32 = heights.iter().sum();
+        let bumpiness: u32 = heights
+            .windows(2)
+            .map(|h| (h[0] as i32 - h[1] as i32).unsigned_abs())
+            .sum();
+
+        w.height * height as f64
+            + w.lines * cleared as f64
+            + w.holes * holes(board, &heights) as f64
+            + w.bumpiness * bumpiness as f64
+            + w.wells * wells(&heights) as f64
+            + w.row_transitions * row_transitions(board) as f64
+            + w.column_transitions * column_transitions(board) as f64
+    }
+
+    /// Lets the bot play `game` until it tops out or `max_pieces` have been placed. Returns the number of
+    /// pieces that were placed.
+    pub fn autoplay(&self, game: &mut Game, max_pieces: u32) -> u32 {
+        for placed in 0..max_pieces {
+            match self.best_placement(&game.board, &game.piece, game.piece_position) {
+                Some(p) => {
+                    game.piece = p.piece;
+                    game.piece_position = p.position;
+                }
+                None => return placed,
+            }
+            if !game.drop_piece() {
+                return placed + 1;
+            }
+        }
+        max_pieces
+    }
+}
+
+fn column_heights(board: &Board) -> [u32; BOARD_WIDTH as usize] {
+    let mut heights = [0; BOARD_WIDTH as usize];
+    for (col, height) in heights.iter_mut().enumerate() {
+        if let Some(row) = (0..BOARD_HEIGHT as usize).find(|&r| board.cells[r][col].is_some()) {
+            *height = BOARD_HEIGHT - row as u32;
+        }
+    }
+    heights
+}
+
+/// Counts the empty cells that have at least one filled cell above them.
+fn holes(board: &Board, heights: &[u32]) -> u32 {
+    let mut holes = 0;
+    for (col, &height) in heights.iter().enumerate() {
+        for row in (BOARD_HEIGHT - height) as usize..BOARD_HEIGHT as usize {
+            if board.cells[row][col].is_none() {
+                holes += 1;
+            }
+        }
+    }
+    holes
+}
+
+/// Sums the depth of every column that is lower than both of its neighbours. Walls count as full height.
+fn wells(heights: &[u32]) -> u32 {
+    let mut wells = 0;
+    for col in 0..heights.len() {
+        let left = if col == 0 {
+            BOARD_HEIGHT
+        } else {
+            heights[col - 1]
+        };
+        let right = heights.get(col + 1).copied().unwrap_or(BOARD_HEIGHT);
+        let rim = left.min(right);
+        if rim > heights[col] {
+            wells += rim - heights[col];
+        }
+    }
+    wells
+}
+
+/// Counts horizontal changes between filled and empty cells. Walls count as filled.
+fn row_transitions(board: &Board) -> u32 {
+    let mut transitions = 0;
+    for row in board.cells.iter() {
+        let mut filled = true;
+        for cell in row.iter() {
+            if cell.is_some() != filled {
+                transitions += 1;
+                filled = cell.is_some();
+            }
+        }
+        if !filled {
+            transitions += 1;
+        }
+    }
+    transitions
+}
+
+/// Counts vertical changes between filled and empty cells. The floor counts as filled.
+fn column_transitions(board: &Board) -> u32 {
+    let mut transitions = 0;
+    for col in 0..BOARD_WIDTH as usize {
+        let mut filled = false;
+        for row in board.cells.iter() {
+            if row[col].is_some() != filled {
+                transitions += 1;
+                filled = row[col].is_some();
+            }
+        }
+        if !filled {
+            transitions += 1;
+        }
+    }
+    transitions
+}
@@ -0,0 +1,218 @@
+use crate::piece::Piece;
+use crate::render::{CellStyle, Renderer};
+use crate::util::*;
+use serde::{Deserialize, Serialize};
+
+pub const BOARD_WIDTH: u32 = 10;
+pub const BOARD_HEIGHT: u32 = 20;
+pub const HIDDEN_ROWS: u32 = 2;
+
+/// Offsets tried in order when turning a piece around, until one fits: in place, one row up, one column
+/// to either side, diagonally up and finally two columns to either side, which gets an I piece off a wall.
+const KICKS_180: [(i32, i32); 8] = [
+    (0, 0),
+    (0, -1),
+    (1, 0),
+    (-1, 0),
+    (1, -1),
+    (-1, -1),
+    (2, 0),
+    (-2, 0),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Board {
+    pub cells: [[Option<Color>; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize],
+}
+
+impl Board {
+    pub fn new() -> Board {
+        Board {
+            cells: [[None; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize],
+        }
+    }
+
+    pub fn render(&self, display: &mut impl Renderer) {
+        let small = display.cell_style() == CellStyle::Small;
+        let (top, bottom) = if small {
+            (HIDDEN_ROWS / 2, BOARD_HEIGHT / 2)
+        } else {
+            (HIDDEN_ROWS, BOARD_HEIGHT)
+        };
+        let (wall, floor) = if display.box_borders() {
+            ("│", "─")
+        } else {
+            ("|", "-")
+        };
+        let right = BOARD_WIDTH * 2 + 1;
+        for y in top..bottom {
+            display.set_text(wall, 0, y, Color::Border, Color::Black);
+            display.set_text(wall, right, y, Color::Border, Color::Black);
+        }
+        for x in 0..right {
+            display.set_text(floor, x, bottom, Color::Border, Color::Black);
+        }
+        if display.box_borders() {
+            display.set_text("└", 0, bottom, Color::Border, Color::Black);
+            display.set_text("┘", right, bottom, Color::Border, Color::Black);
+        }
+
+        for row in 0..BOARD_HEIGHT {
+            for col in 0..BOARD_WIDTH {
+                if let Some(color) = self.cells[row as usize][col as usize] {
+                    draw_cell(
+                        display,
+                        Point {
+                            x: col as i32,
+                            y: row as i32,
+                        },
+                        color,
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn lock_piece(&mut self, piece: &Piece, origin: Point) {
+        piece.each_point(&mut |row, col| {
+            let x = origin.x + col;
+            let y = origin.y + row;
+            self.cells[y as usize][x as usize] = Some(piece.color);
+        });
+    }
+
+    pub fn collision_test(&self, piece: &Piece, origin: Point) -> bool {
+        let mut found = false;
+        piece.each_point(&mut |row, col| {
+            if !found {
+                let x = origin.x + col;
+                let y = origin.y + row;
+                if x < 0
+                    || x >= (BOARD_WIDTH as i32)
+                    || y < 0
+                    || y >= (BOARD_HEIGHT as i32)
+                    || self.cells[y as usize][x as usize].is_some()
+                {
+                    found = true;
+                }
+            }
+        });
+
+        found
+    }
+
+    /// Rotates `piece` at `origin`, kicking it one column away from the nearer wall if it doesn't fit
+    /// in place. Half turns try the offsets in `KICKS_180` instead. Returns the rotated piece and its new
+    /// origin, or None if the rotation is blocked.
+    pub fn rotate_piece(
+        &self,
+        piece: &Piece,
+        origin: Point,
+        direction: Direction,
+    ) -> Option<(Piece, Point)> {
+        let mut rotated = piece.clone();
+        rotated.rotate(direction);
+
+        if direction == Direction::Half {
+            return KICKS_180
+                .iter()
+                .map(|&(x, y)| Point {
+                    x: origin.x + x,
+                    y: origin.y + y,
+                })
+                .find(|&kicked| !self.collision_test(&rotated, kicked))
+                .map(|kicked| (rotated, kicked));
+        }
+        if !self.collision_test(&rotated, origin) {
+            return Some((rotated, origin));
+        }
+
+        let mut kicked = origin;
+        if origin.x < (BOARD_WIDTH / 2) as i32
+        //wallkick left
+        {
+            kicked.x += 1;
+        } else {
+            kicked.x -= 1;
+        }
+        if self.collision_test(&rotated, kicked) && origin.x > (BOARD_WIDTH / 2) as i32
+        //wallkick right
+        {
+            kicked.x -= 1;
+        }
+
+        if self.collision_test(&rotated, kicked) {
+            None
+        } else {
+            Some((rotated, kicked))
+        }
+    }
+
+    /// Returns the rows without gaps, which the next `clear_lines` clears.
+    pub fn full_rows(&self) -> Vec<usize> {
+        (0..self.cells.len())
+            .filter(|&y| !self.cells[y].contains(&None))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.iter().all(|row| row.iter().all(|c| c.is_none()))
+    }
+
+    /// Clears the board of any complete lines, shifting down rows to take their place.
+    /// Returns the total number of lines that were cleared.
+    pub fn clear_lines(&mut self) -> u32 {
+        let mut cleared_lines: usize = 0;
+        for row in (0..self.cells.len()).rev() {
+            if (row as i32) - (cleared_lines as i32) < 0 {
+                break;
+            }
+
+            if cleared_lines > 0 {
+                self.cells[row] = self.cells[row - cleared_lines];
+                self.cells[row - cleared_lines] = [None; BOARD_WIDTH as usize];
+            }
+
+            while !self.cells[row].contains(&None) {
+                cleared_lines += 1;
+                self.cells[row] = self.cells[row - cleared_lines];
+                self.cells[row - cleared_lines] = [None; BOARD_WIDTH as usize];
+            }
+        }
+
+        cleared_lines as u32
+    }
+}
+
+/// Draws a cell of the playfield in `color`.
+pub fn draw_cell(display: &mut impl Renderer, cell: Point, color: Color) {
+    if cell.x < 0 || cell.y < 0 {
+        return;
+    }
+    let x = 1 + 2 * cell.x as u32;
+    if display.cell_style() == CellStyle::Small {
+        display.set_half_cell(x, cell.y as u32 / 2, cell.y % 2 == 1, color);
+    } else {
+        display.set_cell(x, cell.y as u32, color);
+    }
+}
+
+/// Outlines a cell of the playfield in `color`, to show where a piece could go. Small cells are too small
+/// for an outline, so they are drawn in the darker ghost colour instead.
+pub fn draw_outline(display: &mut impl Renderer, cell: Point, color: Color) {
+    if cell.x < 0 || cell.y < 0 {
+        return;
+    }
+    let x = 1 + 2 * cell.x as u32;
+    if display.cell_style() == CellStyle::Small {
+        display.set_half_cell(x, cell.y as u32 / 2, cell.y % 2 == 1, color.shadow());
+    } else {
+        display.set_text("[]", x, cell.y as u32, color, Color::Black);
+    }
+}
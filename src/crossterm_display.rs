@@ -0,0 +1,146 @@
+use crate::render::{Frame, Renderer, Screen, Update};
+use crate::theme::{Depth, TermColor};
+use crate::util::Color;
+use crossterm::cursor::MoveTo;
+use crossterm::event::{DisableBracketedPaste, EnableBracketedPaste};
+use crossterm::style::{self, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::io::{stdout, Write};
+
+/// The 16 colour palette in crossterm's names, in palette order.
+const ANSI16: [style::Color; 16] = [
+    style::Color::Black,
+    style::Color::DarkRed,
+    style::Color::DarkGreen,
+    style::Color::DarkYellow,
+    style::Color::DarkBlue,
+    style::Color::DarkMagenta,
+    style::Color::DarkCyan,
+    style::Color::Grey,
+    style::Color::DarkGrey,
+    style::Color::Red,
+    style::Color::Green,
+    style::Color::Yellow,
+    style::Color::Blue,
+    style::Color::Magenta,
+    style::Color::Cyan,
+    style::Color::White,
+];
+
+/// Renders to the terminal through crossterm, on the alternate screen and with the terminal in raw mode
+/// and bracketed paste on until it is dropped.
+pub struct CrosstermDisplay {
+    frame: Frame,
+    screen: Screen,
+    depth: Depth,
+}
+
+impl CrosstermDisplay {
+    pub fn new(width: u32, height: u32) -> std::io::Result<CrosstermDisplay> {
+        terminal::enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen, EnableBracketedPaste)?;
+        Ok(CrosstermDisplay {
+            frame: Frame::new(width, height),
+            screen: Screen::default(),
+            depth: Depth::detect(),
+        })
+    }
+
+    fn color(&self, color: Color) -> style::Color {
+        match self.depth.convert(self.frame.theme.rgb(color)) {
+            TermColor::Default => style::Color::Reset,
+            TermColor::Rgb(rgb) => style::Color::Rgb {
+                r: rgb.0,
+                g: rgb.1,
+                b: rgb.2,
+            },
+            TermColor::Ansi256(i) => style::Color::AnsiValue(i),
+            TermColor::Ansi16(i) => ANSI16[i as usize],
+        }
+    }
+
+    /// Queues the commands that show the frame. Only the cells that changed since the last frame are
+    /// written.
+    fn queue_frame(&mut self, out: &mut Vec<u8>) -> std::io::Result<()> {
+        let size = terminal::size().unwrap_or_else(|_| self.frame.size());
+        // Colours last set on the terminal. None means unknown.
+        let mut fg_color = None;
+        let mut bg_color = None;
+
+        match self.screen.update(&self.frame, size) {
+            Update::TooSmall(lines) => {
+                if !lines.is_empty() {
+                    queue!(out, ResetColor, Clear(ClearType::All))?;
+                }
+                for (y, line) in lines.iter().enumerate() {
+                    queue!(out, MoveTo(0, y as u16), Print(line))?;
+                }
+            }
+            Update::Draw {
+                clear,
+                left,
+                top,
+                runs,
+            } => {
+                // Reset the colours first, as terminals clear with the current background
+                if clear {
+                    queue!(out, ResetColor, Clear(ClearType::All))?;
+                }
+                for run in runs {
+                    queue!(out, MoveTo((left + run.x) as u16, (top + run.y) as u16))?;
+                    for pixel in run.pixels {
+                        if fg_color != Some(pixel.fg_color) {
+                            fg_color = Some(pixel.fg_color);
+                            queue!(out, SetForegroundColor(self.color(pixel.fg_color)))?;
+                        }
+                        if bg_color != Some(pixel.bg_color) {
+                            bg_color = Some(pixel.bg_color);
+                            queue!(out, SetBackgroundColor(self.color(pixel.bg_color)))?;
+                        }
+                        queue!(out, Print(pixel.c))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Renderer for CrosstermDisplay {
+    fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        &mut self.frame
+    }
+
+    /// Draws the frame centred on the terminal, in a single write.
+    fn render(&mut self) {
+        let mut out = Vec::new();
+        assert!(self.queue_frame(&mut out).is_ok());
+        if !out.is_empty() {
+            let stdout = &mut stdout();
+            assert!(stdout.write_all(&out).is_ok());
+            assert!(stdout.flush().is_ok());
+        }
+    }
+
+    fn fits(&self) -> bool {
+        let (width, height) = self.frame.size();
+        terminal::size().map_or(true, |(w, h)| w >= width && h >= height)
+    }
+}
+
+impl Drop for CrosstermDisplay {
+    fn drop(&mut self) {
+        let _ = execute!(
+            stdout(),
+            ResetColor,
+            DisableBracketedPaste,
+            LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
@@ -1,137 +1,218 @@
+use crate::render::{Frame, Pixel, Renderer, Screen, Update};
+use crate::theme::Depth;
 use crate::util::Color;
 use std::cell::RefCell;
 use std::io::Write;
 
-const ESC: &'static str = "\x1b";
-
-#[derive(Debug)]
-struct Pixel {
-    c: char,
-    fg_color: Color,
-    bg_color: Color,
-}
+const ESC: &str = "\x1b";
 
+/// Renders to a terminal by writing ANSI escape sequences, sizing it up with termion.
 pub struct Display {
-    buffer: Vec<Vec<Pixel>>,
+    frame: Frame,
     writer: RefCell<Box<dyn Write>>,
+    screen: Screen,
+    depth: Depth,
+    /// The size of the terminal, if it is fixed rather than measured with termion.
+    size: Option<(u16, u16)>,
 }
 
 impl Display {
-    pub fn new(width: u32, height: u32, writer: RefCell<Box<dyn Write>>) -> Display {
-        let mut rows = Vec::with_capacity(height as usize);
-        for _ in 0..height {
-            let mut row = Vec::with_capacity(width as usize);
-            for _ in 0..width {
-                row.push(Pixel {
-                    c: ' ',
-                    fg_color: Color::Black,
-                    bg_color: Color::Black,
-                });
-            }
-            rows.push(row);
-        }
-
-        Display {
-            buffer: rows,
+    /// Creates a display writing to `writer`, and turns on bracketed paste until it is dropped. The frame is
+    /// laid out for a terminal of `size`, or of the size termion reports if that is None.
+    pub fn new(
+        width: u32,
+        height: u32,
+        writer: RefCell<Box<dyn Write>>,
+        size: Option<(u16, u16)>,
+    ) -> Display {
+        let display = Display {
+            frame: Frame::new(width, height),
             writer,
-        }
+            screen: Screen::default(),
+            depth: Depth::detect(),
+            size,
+        };
+        display.flush(Self::esc("?2004h").as_bytes());
+        display
     }
 
-    pub fn render(&mut self) {
-        self.clear_screen();
-
-        let mut left = 0;
+    fn flush(&self, out: &[u8]) {
+        if !out.is_empty() {
+            assert!(self.writer.borrow_mut().write_all(out).is_ok());
+            assert!(self.writer.borrow_mut().flush().is_ok());
+        }
+    }
 
-        if let Ok((w, _)) = termion::terminal_size() {
-            //println!("{} {}", self.buffer.len(), self.buffer.len());
-            left = ((0.5 * w as f32) as u32) - ((self.buffer.len() as f32 * 0.5) as u32);
+    fn write_pixel(
+        &self,
+        out: &mut Vec<u8>,
+        pixel: &Pixel,
+        fg_color: &mut Option<Color>,
+        bg_color: &mut Option<Color>,
+    ) {
+        let theme = &self.frame.theme;
+        if *fg_color != Some(pixel.fg_color) {
+            *fg_color = Some(pixel.fg_color);
+            let sgr = self.depth.convert(theme.rgb(pixel.fg_color)).sgr(false);
+            out.extend(Self::esc(&format!("{}m", sgr)).as_bytes());
+        }
+        if *bg_color != Some(pixel.bg_color) {
+            *bg_color = Some(pixel.bg_color);
+            let sgr = self.depth.convert(theme.rgb(pixel.bg_color)).sgr(true);
+            out.extend(Self::esc(&format!("{}m", sgr)).as_bytes());
         }
+        out.extend(pixel.c.encode_utf8(&mut [0; 4]).as_bytes());
+    }
 
-        self.set_cursor_pos(left, 0);
+    fn terminal_size(&self) -> Option<(u16, u16)> {
+        self.size.or_else(|| termion::terminal_size().ok())
+    }
 
-        let mut fg_color = Color::Black;
-        let mut bg_color = Color::Black;
+    fn esc(text: &str) -> String {
+        format!("{}[{}", ESC, text)
+    }
+}
+
+impl Renderer for Display {
+    fn frame(&self) -> &Frame {
+        &self.frame
+    }
 
-        let mut y = 0;
+    fn frame_mut(&mut self) -> &mut Frame {
+        &mut self.frame
+    }
 
-        for row in &self.buffer {
-            for pixel in row {
-                if pixel.fg_color != fg_color {
-                    fg_color = pixel.fg_color;
-                    self.set_fg_color(pixel.fg_color);
+    /// Draws the frame, centred on the terminal. Only the cells that changed since the last frame are
+    /// written, all in a single write. Output that isn't a terminal is treated as exactly the frame's size.
+    fn render(&mut self) {
+        let size = self.terminal_size().unwrap_or_else(|| self.frame.size());
+        let mut out: Vec<u8> = Vec::new();
+        // Colours last set on the terminal. None means unknown.
+        let mut fg_color = None;
+        let mut bg_color = None;
+
+        match self.screen.update(&self.frame, size) {
+            Update::TooSmall(lines) => {
+                if !lines.is_empty() {
+                    out.extend(Self::esc("0m").as_bytes());
+                    out.extend(Self::esc("2J").as_bytes());
                 }
-                if pixel.bg_color != bg_color {
-                    bg_color = pixel.bg_color;
-                    self.set_bg_color(pixel.bg_color);
+                for (y, line) in lines.iter().enumerate() {
+                    out.extend(Self::esc(&format!("{};1H", y + 1)).as_bytes());
+                    out.extend(line.as_bytes());
+                }
+            }
+            Update::Draw {
+                clear,
+                left,
+                top,
+                runs,
+            } => {
+                // Reset the colours first, as terminals clear with the current background
+                if clear {
+                    out.extend(Self::esc("0m").as_bytes());
+                    out.extend(Self::esc("2J").as_bytes());
+                }
+                for run in runs {
+                    let position = format!("{};{}H", top + run.y + 1, left + run.x + 1);
+                    out.extend(Self::esc(&position).as_bytes());
+                    for pixel in run.pixels {
+                        self.write_pixel(&mut out, pixel, &mut fg_color, &mut bg_color);
+                    }
                 }
-
-                let bytes = [pixel.c as u8];
-                assert!(self.writer.borrow_mut().write_all(&bytes).is_ok());
             }
-            y += 1;
-            self.set_cursor_pos(left, y);
         }
-
-        assert!(self.writer.borrow_mut().flush().is_ok());
+        self.flush(&out);
     }
 
-    pub fn set_text<S: AsRef<str>>(
-        &mut self,
-        text: S,
-        x: u32,
-        y: u32,
-        fg_color: Color,
-        bg_color: Color,
-    ) {
-        let row = &mut self.buffer[y as usize];
-        let mut i = 0;
-
-        for c in text.as_ref().chars() {
-            let cell = &mut row[(x + i) as usize];
-            cell.c = c;
-            cell.fg_color = fg_color;
-            cell.bg_color = bg_color;
-            i += 1;
-        }
+    /// Whether the whole frame fits on the terminal. Output that isn't a terminal always fits.
+    fn fits(&self) -> bool {
+        let (width, height) = self.frame.size();
+        self.terminal_size()
+            .is_none_or(|(w, h)| w >= width && h >= height)
     }
+}
 
-    pub fn clear_screen(&self) {
-        assert!(self
+impl Drop for Display {
+    fn drop(&mut self) {
+        let _ = self
             .writer
             .borrow_mut()
-            .write_all(self.esc("2J").as_bytes())
-            .is_ok());
-        assert!(self.writer.borrow_mut().flush().is_ok());
+            .write_all(Self::esc("?2004l").as_bytes());
+        let _ = self.writer.borrow_mut().flush();
     }
+}
 
-    pub fn clear_buffer(&mut self) {
-        for row in 0..self.buffer.len() {
-            for col in 0..self.buffer[row].len() {
-                self.buffer[row][col].c = ' ';
-                self.buffer[row][col].fg_color = Color::Black;
-                self.buffer[row][col].bg_color = Color::Black;
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    /// A writer whose output can be read back after the display has written it.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Output {
+        fn take(&self) -> String {
+            String::from_utf8(std::mem::take(&mut *self.0.borrow_mut())).unwrap()
         }
     }
 
-    fn set_cursor_pos(&self, x: u32, y: u32) {
-        // Console positions are 1-based
-        self.print(&self.esc(&format!("{};{}H", y + 1, x + 1)));
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
     }
 
-    fn esc(&self, text: &str) -> String {
-        format!("{}[{}", ESC, text)
+    /// A 20x5 display on a terminal of `size`, with the output it wrote on creation discarded.
+    fn display(size: (u16, u16)) -> (Display, Output) {
+        let output = Output::default();
+        let display = Display::new(20, 5, RefCell::new(Box::new(output.clone())), Some(size));
+        output.take();
+        (display, output)
     }
 
-    fn print(&self, text: &str) {
-        assert!(self.writer.borrow_mut().write_all(text.as_bytes()).is_ok());
+    #[test]
+    fn unchanged_frame_writes_nothing() {
+        let (mut display, output) = display((20, 5));
+        display.set_text("Score: 0", 1, 1, Color::Text, Color::Black);
+        display.render();
+        assert!(output.take().contains("Score: 0"));
+        display.render();
+        assert_eq!(output.take(), "");
     }
 
-    fn set_fg_color(&self, color: Color) {
-        self.print(&self.esc(&format!("38;5;{}m", color as i32)));
+    #[test]
+    fn changed_cell_is_written_alone() {
+        let (mut display, output) = display((30, 9));
+        display.set_text("Score: 0", 1, 1, Color::Text, Color::Black);
+        display.render();
+        output.take();
+
+        display.set_text("Score: 5", 1, 1, Color::Text, Color::Black);
+        display.render();
+        let out = output.take();
+        // The frame is centred, so its column 8 on line 1 is column 14 on line 4 of the terminal.
+        assert!(out.starts_with("\x1b[4;14H"), "{:?}", out);
+        // One cursor move, then the text and background colours, which are unknown at the start of a frame.
+        assert_eq!(out.matches("\x1b[").count(), 3, "{:?}", out);
+        assert!(out.ends_with('5'), "{:?}", out);
     }
 
-    fn set_bg_color(&self, color: Color) {
-        self.print(&self.esc(&format!("48;5;{}m", color as i32)));
+    #[test]
+    fn clear_resets_colours_first() {
+        let (mut display, output) = display((20, 5));
+        display.set_text("Score: 0", 1, 1, Color::Text, Color::Black);
+        display.render();
+        assert!(output.take().starts_with("\x1b[0m\x1b[2J"));
+
+        display.size = Some((30, 9));
+        display.render();
+        assert!(output.take().starts_with("\x1b[0m\x1b[2J"));
     }
 }
@@ -0,0 +1,377 @@
+use crate::board::*;
+use crate::fumen::{self, Page};
+use crate::input::{self, Event};
+use crate::keymap::Key;
+use crate::piece::*;
+use crate::puzzle::{self, Goal, Puzzle, PuzzleError};
+use crate::render::Renderer;
+use crate::util::*;
+use std::path::Path;
+
+/// What the number keys select: the seven pieces, then garbage.
+const BRUSHES: [Option<PieceKind>; 8] = [
+    Some(PieceKind::I),
+    Some(PieceKind::O),
+    Some(PieceKind::T),
+    Some(PieceKind::S),
+    Some(PieceKind::Z),
+    Some(PieceKind::J),
+    Some(PieceKind::L),
+    None,
+];
+
+const GOALS: [Goal; 8] = [
+    Goal::Lines(1),
+    Goal::Lines(2),
+    Goal::Lines(3),
+    Goal::Lines(4),
+    Goal::TSpin(1),
+    Goal::TSpin(2),
+    Goal::TSpin(3),
+    Goal::PerfectClear,
+];
+
+/// The editor's keys are fixed; the keymap only applies to games.
+const HELP: [&str; 7] = [
+    "Arrows/WASD: move  Space: paint/place",
+    "X: erase  1-8: brush (IOTSZJL, garbage)",
+    "F: piece mode  Q/E: rotate piece",
+    "N: add to queue  Backspace: remove",
+    "C: set hold  G: next goal",
+    "V: save  Z: quit",
+    "(These keys ignore keys.toml)",
+];
+
+/// Paints boards and sets up queues for puzzles and fumens.
+pub struct Editor {
+    board: Board,
+    cursor: Point,
+    brush: usize,
+    /// The piece being positioned at the cursor in piece mode.
+    piece: Option<Piece>,
+    queue: Vec<PieceKind>,
+    hold: Option<PieceKind>,
+    /// Any goal a puzzle file can have, though G only cycles through `GOALS`.
+    goal: Goal,
+    name: String,
+    path: String,
+    message: String,
+}
+
+impl Editor {
+    /// Opens the puzzle or fumen saved at `path`, or starts an empty board if there is no such file. Files
+    /// starting with a fumen prefix are read as fumens, anything else as a puzzle.
+    pub fn open(path: &str) -> Result<Editor, PuzzleError> {
+        let name = Path::new(path)
+            .file_stem()
+            .map_or("Untitled".to_string(), |s| s.to_string_lossy().to_string());
+        let mut editor = Editor {
+            board: Board::new(),
+            cursor: Point {
+                x: BOARD_WIDTH as i32 / 2,
+                y: BOARD_HEIGHT as i32 - 1,
+            },
+            brush: 0,
+            piece: None,
+            queue: Vec::new(),
+            hold: None,
+            goal: GOALS[0],
+            name,
+            path: path.to_string(),
+            message: String::new(),
+        };
+        if !Path::new(path).exists() {
+            editor.message = format!("New file {}", path);
+            return Ok(editor);
+        }
+
+        let text = std::fs::read_to_string(path).map_err(PuzzleError::Io)?;
+        if is_fumen(&text) {
+            editor.load_fumen(&text).map_err(PuzzleError::Invalid)?;
+        } else {
+            let puzzle = puzzle::parse(&text)?;
+            editor.board = puzzle.board;
+            editor.queue = puzzle.queue;
+            editor.hold = puzzle.hold;
+            editor.goal = puzzle.goal;
+            editor.name = puzzle.name;
+        }
+        editor.message = format!("Opened {}", path);
+        Ok(editor)
+    }
+
+    /// Runs the editor until the player quits.
+    pub fn run(&mut self, display: &mut impl Renderer) {
+        loop {
+            display.clear_buffer();
+            self.render(display);
+            display.render();
+
+            match input::read(None) {
+                Some(Event::Key(Key::Char('z'))) | Some(Event::Key(Key::Ctrl('c'))) => break,
+                Some(Event::Key(key)) => self.keypress(key),
+                Some(Event::Paste(text)) => self.paste(&text),
+                None => {}
+            }
+        }
+    }
+
+    /// Returns the board, with the piece being positioned if there is one, as a single-page fumen.
+    pub fn fumen(&self) -> String {
+        fumen::encode(&[Page {
+            piece: self.piece.clone().map(|p| (p, self.cursor)),
+            comment: self.name.clone(),
+            lock: false,
+            ..Page::new(self.board.clone())
+        }])
+    }
+
+    /// Replaces the board with the first page of a fumen.
+    fn load_fumen(&mut self, text: &str) -> Result<(), String> {
+        let page = fumen::decode(text)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .next()
+            .ok_or("the fumen has no pages")?;
+        self.board = page.board;
+        self.set_piece(None);
+        if let Some((piece, origin)) = page.piece {
+            self.brush = BRUSHES.iter().position(|&b| b == Some(piece.kind)).unwrap();
+            self.cursor = origin;
+            self.set_piece(Some(piece));
+        }
+        if !page.comment.is_empty() {
+            self.name = page.comment;
+        }
+        Ok(())
+    }
+
+    /// Loads a fumen pasted into the terminal.
+    fn paste(&mut self, text: &str) {
+        self.message = if !is_fumen(text) {
+            "Only fumens can be pasted".to_string()
+        } else {
+            match self.load_fumen(text) {
+                Ok(()) => "Pasted fumen".to_string(),
+                Err(e) => format!("Could not paste: {}", e),
+            }
+        };
+    }
+
+    fn brush_color(&self) -> Color {
+        BRUSHES[self.brush].map_or(Color::Gray, |k| Piece::new(k).color)
+    }
+
+    fn keypress(&mut self, key: Key) {
+        self.message.clear();
+        match key {
+            Key::Left | Key::Char('a') => self.move_cursor(-1, 0),
+            Key::Right | Key::Char('d') => self.move_cursor(1, 0),
+            Key::Up | Key::Char('w') => self.move_cursor(0, -1),
+            Key::Down | Key::Char('s') => self.move_cursor(0, 1),
+            Key::Char(' ') => self.paint(),
+            Key::Char('c') => {
+                let brush = BRUSHES[self.brush];
+                self.hold = if self.hold == brush { None } else { brush };
+            }
+            Key::Char('x') if self.piece.is_none() => {
+                self.board.cells[self.cursor.y as usize][self.cursor.x as usize] = None;
+            }
+            Key::Char(c @ '1'..='8') => {
+                let brush = c as usize - '1' as usize;
+                match (BRUSHES[brush], &self.piece) {
+                    (Some(kind), Some(_)) => self.set_piece(Some(Piece::new(kind))),
+                    (None, Some(_)) => return,
+                    _ => {}
+                }
+                self.brush = brush;
+            }
+            Key::Char('f') => {
+                let piece = match (&self.piece, BRUSHES[self.brush]) {
+                    (None, Some(kind)) => Some(Piece::new(kind)),
+                    _ => None,
+                };
+                self.set_piece(piece);
+            }
+            Key::Char('q') | Key::Char('e') => {
+                let direction = if let Key::Char('q') = key {
+                    Direction::Left
+                } else {
+                    Direction::Right
+                };
+                if let Some(mut piece) = self.piece.take() {
+                    piece.rotate(direction);
+                    self.set_piece(Some(piece));
+                }
+            }
+            Key::Char('n') => {
+                if let Some(kind) = BRUSHES[self.brush] {
+                    self.queue.push(kind);
+                }
+            }
+            Key::Backspace => {
+                self.queue.pop();
+            }
+            Key::Char('g') => {
+                let next = GOALS
+                    .iter()
+                    .position(|&g| g == self.goal)
+                    .map_or(0, |i| i + 1);
+                self.goal = GOALS[next % GOALS.len()];
+            }
+            Key::Char('v') => {
+                self.message = match self.save() {
+                    Ok(()) => format!("Saved {}", self.path),
+                    Err(e) => e,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Moves the cursor, keeping the cell or the whole piece under it on the board.
+    fn move_cursor(&mut self, dx: i32, dy: i32) {
+        let moved = Point {
+            x: self.cursor.x + dx,
+            y: self.cursor.y + dy,
+        };
+        match &self.piece {
+            Some(piece) if piece.cells(moved).iter().all(|&c| on_board(c)) => self.cursor = moved,
+            Some(_) => {}
+            None if on_board(moved) => self.cursor = moved,
+            None => {}
+        }
+    }
+
+    /// Switches to piece mode with `piece`, or back to paint mode, and shifts the cursor so that everything
+    /// under it is on the board.
+    fn set_piece(&mut self, piece: Option<Piece>) {
+        self.piece = piece;
+        let cells = match &self.piece {
+            Some(piece) => piece.cells(self.cursor),
+            None => vec![self.cursor],
+        };
+        let shift = |coords: Vec<i32>, size: u32| {
+            let (min, max) = (*coords.iter().min().unwrap(), *coords.iter().max().unwrap());
+            if min < 0 {
+                -min
+            } else {
+                (size as i32 - 1 - max).min(0)
+            }
+        };
+        self.cursor.x += shift(cells.iter().map(|c| c.x).collect(), BOARD_WIDTH);
+        self.cursor.y += shift(cells.iter().map(|c| c.y).collect(), BOARD_HEIGHT);
+    }
+
+    /// Paints the cell under the cursor, or erases it if it already has the brush's colour. In piece mode
+    /// the piece is placed on the board instead.
+    fn paint(&mut self) {
+        if let Some(piece) = &self.piece {
+            if self.board.collision_test(piece, self.cursor) {
+                self.message = "The piece doesn't fit there".to_string();
+            } else {
+                self.board.lock_piece(piece, self.cursor);
+            }
+            return;
+        }
+        let color = self.brush_color();
+        let cell = &mut self.board.cells[self.cursor.y as usize][self.cursor.x as usize];
+        *cell = if *cell == Some(color) {
+            None
+        } else {
+            Some(color)
+        };
+    }
+
+    /// Saves a puzzle if the file name ends in `.toml` and a fumen otherwise.
+    fn save(&self) -> Result<(), String> {
+        let text = if self.path.ends_with(".toml") {
+            if self.queue.is_empty() {
+                return Err("Add pieces to the queue with N before saving".to_string());
+            }
+            puzzle::to_toml(&Puzzle {
+                name: self.name.clone(),
+                goal: self.goal,
+                queue: self.queue.clone(),
+                hold: self.hold,
+                board: self.board.clone(),
+            })
+        } else {
+            self.fumen() + "\n"
+        };
+        std::fs::write(&self.path, text).map_err(|e| format!("Could not save: {}", e))
+    }
+
+    fn render(&self, display: &mut impl Renderer) {
+        self.board.render(display);
+
+        match &self.piece {
+            Some(piece) => {
+                for cell in piece
+                    .cells(self.cursor)
+                    .into_iter()
+                    .filter(|&c| on_board(c))
+                {
+                    draw_outline(display, cell, piece.color);
+                }
+            }
+            None => draw_outline(display, self.cursor, self.brush_color()),
+        }
+
+        let left_margin = BOARD_WIDTH * 2 + 5;
+        display.set_text(&self.name, left_margin, 1, Color::Text, Color::Black);
+        let brush = BRUSHES[self.brush].map_or("Garbage", |k| k.name());
+        let mode = if self.piece.is_some() {
+            "piece"
+        } else {
+            "paint"
+        };
+        display.set_text(
+            format!("Brush: {} ({})", brush, mode),
+            left_margin,
+            3,
+            self.brush_color(),
+            Color::Black,
+        );
+        let queue: String = self.queue.iter().map(|k| k.name()).collect();
+        display.set_text(
+            format!("Queue: {}", queue),
+            left_margin,
+            5,
+            Color::Text,
+            Color::Black,
+        );
+        display.set_text(
+            format!("Hold: {}", self.hold.map_or("-", |k| k.name())),
+            left_margin,
+            7,
+            Color::Text,
+            Color::Black,
+        );
+        display.set_text(
+            format!("Goal: {}", self.goal.describe()),
+            left_margin,
+            9,
+            Color::Text,
+            Color::Black,
+        );
+        for (i, line) in HELP.iter().enumerate() {
+            display.set_text(line, left_margin, 12 + i as u32, Color::Text, Color::Black);
+        }
+        display.set_text(
+            &self.message,
+            left_margin,
+            20,
+            Color::Highlight,
+            Color::Black,
+        );
+    }
+}
+
+fn is_fumen(text: &str) -> bool {
+    text.trim().get(1..5) == Some("115@")
+}
+
+fn on_board(cell: Point) -> bool {
+    cell.x >= 0 && cell.x < BOARD_WIDTH as i32 && cell.y >= 0 && cell.y < BOARD_HEIGHT as i32
+}
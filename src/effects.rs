@@ -0,0 +1,149 @@
+use crate::board::*;
+use crate::render::{CellStyle, Renderer};
+use crate::util::Color;
+use std::time::{Duration, Instant};
+
+/// How often the game is drawn while an effect plays.
+pub const FRAME: Duration = Duration::from_millis(40);
+
+/// How long a locked piece flashes.
+const LOCK_FLASH: Duration = Duration::from_millis(100);
+
+/// Milliseconds each colour of a flash is shown.
+const FLASH_STEP: u128 = 80;
+
+/// Share of a line clear spent flashing the full rows, before the rows above collapse into their place.
+const FLASH_SHARE: f64 = 0.6;
+
+/// Colours the rows of a Tetris or a perfect clear cycle through.
+const RAINBOW: [Color; 7] = [
+    Color::Red,
+    Color::Orange,
+    Color::Yellow,
+    Color::Green,
+    Color::Cyan,
+    Color::Blue,
+    Color::Purple,
+];
+
+/// A short animation drawn over the board after a piece locks.
+pub struct Effect {
+    start: Instant,
+    /// The cells of the piece that locked, which flash briefly.
+    piece: Vec<Point>,
+    clear: Option<Clear>,
+}
+
+/// Full rows flashing and then collapsing.
+struct Clear {
+    /// The board with the piece locked, before the rows were cleared.
+    board: Board,
+    rows: Vec<usize>,
+    /// Whether nothing but the full rows is left on the board.
+    perfect: bool,
+    duration: Duration,
+}
+
+impl Effect {
+    /// Flashes the cells of a piece that just locked.
+    pub fn lock(piece: Vec<Point>) -> Effect {
+        Effect {
+            start: Instant::now(),
+            piece,
+            clear: None,
+        }
+    }
+
+    /// Flashes the piece that filled `rows` of `board`, then the rows, and collapses the rows above into
+    /// their place, all within `duration`.
+    pub fn clear(piece: Vec<Point>, board: Board, rows: Vec<usize>, duration: Duration) -> Effect {
+        let perfect = board
+            .cells
+            .iter()
+            .enumerate()
+            .all(|(y, row)| rows.contains(&y) || row.iter().all(Option::is_none));
+        Effect {
+            start: Instant::now(),
+            piece,
+            clear: Some(Clear {
+                board,
+                rows,
+                perfect,
+                duration,
+            }),
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        let duration = self.clear.as_ref().map_or(LOCK_FLASH, |c| c.duration);
+        self.start.elapsed() >= duration.max(LOCK_FLASH)
+    }
+
+    /// Draws `board`, the board as it is now, with the effect over it.
+    pub fn render(&self, board: &Board, display: &mut impl Renderer) {
+        let elapsed = self.start.elapsed();
+        let flashing = match &self.clear {
+            Some(clear) if elapsed < clear.duration => clear.render(elapsed, display),
+            _ => {
+                board.render(display);
+                true
+            }
+        };
+        if flashing && elapsed < LOCK_FLASH {
+            for &cell in &self.piece {
+                draw_cell(display, cell, Color::Highlight);
+            }
+        }
+    }
+}
+
+impl Clear {
+    /// Draws the clear as it is `elapsed` into it. Returns true while the rows are still flashing.
+    fn render(&self, elapsed: Duration, display: &mut impl Renderer) -> bool {
+        let flash_time = self.duration.mul_f64(FLASH_SHARE);
+        let mut board = self.board.clone();
+        let flashing = elapsed < flash_time;
+        if flashing {
+            let step = (elapsed.as_millis() / FLASH_STEP) as usize;
+            let color = if self.perfect || self.rows.len() == 4 {
+                Some(RAINBOW[step % RAINBOW.len()])
+            } else if step.is_multiple_of(2) {
+                Some(Color::Highlight)
+            } else {
+                None
+            };
+            for &y in &self.rows {
+                board.cells[y] = [color; BOARD_WIDTH as usize];
+            }
+        } else {
+            // Every row falls by the number of cleared rows below it. Rounding down keeps rows from
+            // overtaking each other.
+            let progress = (elapsed - flash_time).as_secs_f64()
+                / (self.duration - flash_time).as_secs_f64().max(f64::EPSILON);
+            board = Board::new();
+            for (y, row) in self.board.cells.iter().enumerate() {
+                if self.rows.contains(&y) {
+                    continue;
+                }
+                let fall = self.rows.iter().filter(|&&r| r > y).count();
+                board.cells[y + (fall as f64 * progress.min(1.0)) as usize] = *row;
+            }
+        }
+        board.render(display);
+
+        let label = if self.perfect {
+            "PERFECT CLEAR"
+        } else if self.rows.len() == 4 {
+            "TETRIS"
+        } else {
+            ""
+        };
+        let mut y = BOARD_HEIGHT / 2;
+        if display.cell_style() == CellStyle::Small {
+            y /= 2;
+        }
+        let x = 1 + (BOARD_WIDTH * 2 - label.len() as u32) / 2;
+        display.set_text(label, x, y, Color::Highlight, Color::Black);
+        flashing
+    }
+}
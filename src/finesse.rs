@@ -0,0 +1,337 @@
+use crate::board::*;
+use crate::piece::*;
+use crate::util::Direction;
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Terminals don't report key releases, so a held key shows up as a stream of presses. Presses of the same
+/// key closer together than this are treated as auto-repeat rather than separate taps.
+const AUTOREPEAT: Duration = Duration::from_millis(100);
+
+/// A single input in an optimal key sequence. DAS inputs hold the key until the piece hits an obstacle.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Input {
+    Left,
+    Right,
+    DasLeft,
+    DasRight,
+    RotateLeft,
+    RotateRight,
+    Rotate180,
+    Drop,
+}
+
+impl Input {
+    pub fn name(self) -> &'static str {
+        match self {
+            Input::Left => "L",
+            Input::Right => "R",
+            Input::DasLeft => "DAS-L",
+            Input::DasRight => "DAS-R",
+            Input::RotateLeft => "CCW",
+            Input::RotateRight => "CW",
+            Input::Rotate180 => "180",
+            Input::Drop => "Drop",
+        }
+    }
+}
+
+/// Returns the shortest sequence of inputs that takes `piece` from `spawn` to a hard drop covering exactly
+/// the `target` cells, or None if the target can't be reached without soft dropping (tucks and spins). The
+/// 180° rotation is only used if `rotate_180` says the rules allow it.
+pub fn optimal_inputs(
+    board: &Board,
+    piece: &Piece,
+    spawn: Point,
+    target: &[Point],
+    rotate_180: bool,
+) -> Option<Vec<Input>> {
+    let mut target = target.to_vec();
+    target.sort_by_key(|p| (p.y, p.x));
+
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    seen.insert((piece.shape.clone(), spawn.x, spawn.y));
+    queue.push_back((piece.clone(), spawn, Vec::new()));
+
+    while let Some((piece, position, inputs)) = queue.pop_front() {
+        let mut dropped = position;
+        while !board.collision_test(
+            &piece,
+            Point {
+                x: dropped.x,
+                y: dropped.y + 1,
+            },
+        ) {
+            dropped.y += 1;
+        }
+        let mut cells = piece.cells(dropped);
+        cells.sort_by_key(|p| (p.y, p.x));
+        if cells == target {
+            let mut inputs = inputs;
+            inputs.push(Input::Drop);
+            return Some(inputs);
+        }
+
+        let mut moves = vec![
+            Input::Left,
+            Input::Right,
+            Input::DasLeft,
+            Input::DasRight,
+            Input::RotateLeft,
+            Input::RotateRight,
+        ];
+        if rotate_180 {
+            moves.push(Input::Rotate180);
+        }
+        for &input in moves.iter() {
+            if let Some((next, next_position)) = apply(board, &piece, position, input) {
+                if seen.insert((next.shape.clone(), next_position.x, next_position.y)) {
+                    let mut next_inputs = inputs.clone();
+                    next_inputs.push(input);
+                    queue.push_back((next, next_position, next_inputs));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+fn apply(board: &Board, piece: &Piece, position: Point, input: Input) -> Option<(Piece, Point)> {
+    let shift = |dx: i32| Point {
+        x: position.x + dx,
+        y: position.y,
+    };
+    match input {
+        Input::Left | Input::Right => {
+            let moved = shift(if input == Input::Left { -1 } else { 1 });
+            if board.collision_test(piece, moved) {
+                None
+            } else {
+                Some((piece.clone(), moved))
+            }
+        }
+        Input::DasLeft | Input::DasRight => {
+            let dx = if input == Input::DasLeft { -1 } else { 1 };
+            let mut moved = position;
+            while !board.collision_test(
+                piece,
+                Point {
+                    x: moved.x + dx,
+                    y: moved.y,
+                },
+            ) {
+                moved.x += dx;
+            }
+            Some((piece.clone(), moved))
+        }
+        Input::RotateLeft => board.rotate_piece(piece, position, Direction::Left),
+        Input::RotateRight => board.rotate_piece(piece, position, Direction::Right),
+        Input::Rotate180 => board.rotate_piece(piece, position, Direction::Half),
+        Input::Drop => None,
+    }
+}
+
+/// A series of presses of the same movement key.
+#[derive(Clone)]
+struct Run {
+    dx: i32,
+    presses: u32,
+    last: Instant,
+    held: bool,
+}
+
+/// Counts the inputs the player uses for each piece and compares them with the optimal sequence once the
+/// piece locks.
+#[derive(Clone)]
+pub struct FinesseTracker {
+    spawn: Option<(Piece, Point)>,
+    inputs: u32,
+    run: Option<Run>,
+    pub pieces: [u32; 7],
+    pub faults: [u32; 7],
+    /// The optimal inputs for the most recent piece, if the player used more than that.
+    pub last_fault: Option<Vec<Input>>,
+}
+
+impl FinesseTracker {
+    pub fn new() -> FinesseTracker {
+        FinesseTracker {
+            spawn: None,
+            inputs: 0,
+            run: None,
+            pieces: [0; 7],
+            faults: [0; 7],
+            last_fault: None,
+        }
+    }
+
+    /// Starts counting inputs for a freshly spawned (or swapped in) piece.
+    pub fn spawned(&mut self, piece: &Piece, position: Point) {
+        self.spawn = Some((piece.clone(), position));
+        self.inputs = 0;
+        self.run = None;
+    }
+
+    /// Records a press of a movement key. A run of presses that turns out to be auto-repeat only counts as a
+    /// single (DAS) input.
+    pub fn moved(&mut self, dx: i32) {
+        let now = Instant::now();
+        if let Some(run) = self.run.as_mut() {
+            if run.dx == dx {
+                if now - run.last < AUTOREPEAT {
+                    if !run.held {
+                        self.inputs -= run.presses - 1;
+                        run.held = true;
+                    }
+                    run.last = now;
+                    return;
+                } else if !run.held {
+                    self.inputs += 1;
+                    run.presses += 1;
+                    run.last = now;
+                    return;
+                }
+            }
+        }
+        self.inputs += 1;
+        self.run = Some(Run {
+            dx,
+            presses: 1,
+            last: now,
+            held: false,
+        });
+    }
+
+    pub fn rotated(&mut self) {
+        self.inputs += 1;
+        self.run = None;
+    }
+
+    /// Scores the piece that just locked at `position` on `board` (the board before the lock), against the
+    /// inputs the rules allow. Returns true if the player made a finesse fault.
+    pub fn locked(
+        &mut self,
+        board: &Board,
+        piece: &Piece,
+        position: Point,
+        rotate_180: bool,
+    ) -> bool {
+        let index = piece.kind as usize;
+        self.pieces[index] += 1;
+        self.last_fault = None;
+
+        // Dropping a piece without touching it can never be beaten.
+        let spawn = self.spawn.take();
+        if self.inputs == 0 {
+            return false;
+        }
+
+        if let Some((spawn_piece, spawn_position)) = spawn {
+            let target = piece.cells(position);
+            if let Some(optimal) =
+                optimal_inputs(board, &spawn_piece, spawn_position, &target, rotate_180)
+            {
+                if self.inputs + 1 > optimal.len() as u32 {
+                    self.faults[index] += 1;
+                    self.last_fault = Some(optimal);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    pub fn total_faults(&self) -> u32 {
+        self.faults.iter().sum()
+    }
+
+    pub fn total_pieces(&self) -> u32 {
+        self.pieces.iter().sum()
+    }
+
+    /// Summarises the fault rate for every piece type that was played.
+    pub fn report(&self) -> Vec<String> {
+        PieceKind::ALL
+            .iter()
+            .filter(|k| self.pieces[**k as usize] > 0)
+            .map(|k| {
+                let (faults, pieces) = (self.faults[*k as usize], self.pieces[*k as usize]);
+                format!(
+                    "{}: {} faults in {} pieces ({:.0}%)",
+                    k.name(),
+                    faults,
+                    pieces,
+                    100.0 * faults as f64 / pieces as f64
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPAWN: Point = Point { x: 3, y: 0 };
+
+    /// The inputs, not counting the drop, that take a T from its spawn to column `x` after `turns` turns to
+    /// the right.
+    fn t_inputs(turns: usize, x: i32, rotate_180: bool) -> usize {
+        let board = Board::new();
+        let mut piece = Piece::new_t();
+        (0..turns).for_each(|_| piece.rotate(Direction::Right));
+        let mut position = Point { x, y: 0 };
+        while !board.collision_test(
+            &piece,
+            Point {
+                x,
+                y: position.y + 1,
+            },
+        ) {
+            position.y += 1;
+        }
+        let target = piece.cells(position);
+        let inputs = optimal_inputs(&board, &Piece::new_t(), SPAWN, &target, rotate_180).unwrap();
+        assert_eq!(inputs.last(), Some(&Input::Drop));
+        inputs.len() - 1
+    }
+
+    #[test]
+    fn flat_t_in_every_column() {
+        let expected = [1, 2, 1, 0, 1, 2, 2, 1];
+        for (x, &inputs) in expected.iter().enumerate() {
+            assert_eq!(t_inputs(0, x as i32, false), inputs, "column {}", x);
+            assert_eq!(t_inputs(0, x as i32, true), inputs, "column {}", x);
+        }
+    }
+
+    #[test]
+    fn upside_down_t_takes_one_turn_with_180() {
+        let flat = [1, 2, 1, 0, 1, 2, 2, 1];
+        for (x, &inputs) in flat.iter().enumerate() {
+            assert_eq!(t_inputs(2, x as i32, false), inputs + 2, "column {}", x);
+            assert_eq!(t_inputs(2, x as i32, true), inputs + 1, "column {}", x);
+        }
+    }
+
+    #[test]
+    fn double_turn_is_a_fault_only_with_180() {
+        let board = Board::new();
+        let mut piece = Piece::new_t();
+        piece.rotate(Direction::Half);
+        for &(rotate_180, fault) in [(false, false), (true, true)].iter() {
+            let mut tracker = FinesseTracker::new();
+            tracker.spawned(&Piece::new_t(), SPAWN);
+            tracker.rotated();
+            tracker.rotated();
+            let position = Point { x: 3, y: 17 };
+            assert_eq!(tracker.locked(&board, &piece, position, rotate_180), fault);
+            assert_eq!(
+                tracker.last_fault,
+                Some(vec![Input::Rotate180, Input::Drop]).filter(|_| fault)
+            );
+        }
+    }
+}
@@ -0,0 +1,760 @@
+use crate::board::*;
+use crate::piece::*;
+use crate::util::{Color, Direction};
+use std::fmt;
+
+const ENCODE_TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Fumen fields have 23 lines above the floor plus a garbage line below it.
+const FIELD_TOP: u32 = 23;
+const FIELD_BLOCKS: u32 = (FIELD_TOP + 1) * BOARD_WIDTH;
+
+/// Comments are stored four characters to a value, in base 96 over printable ASCII.
+const COMMENT_BASE: u32 = 96;
+
+/// The piece each fumen block number stands for. 0 is an empty cell and 8 a garbage cell.
+const KINDS: [Option<PieceKind>; 8] = [
+    None,
+    Some(PieceKind::I),
+    Some(PieceKind::L),
+    Some(PieceKind::O),
+    Some(PieceKind::Z),
+    Some(PieceKind::T),
+    Some(PieceKind::J),
+    Some(PieceKind::S),
+];
+const GARBAGE: u8 = 8;
+
+/// Fumen rotations in the order they are numbered.
+const REVERSE: u32 = 0;
+const RIGHT: u32 = 1;
+const SPAWN: u32 = 2;
+const LEFT: u32 = 3;
+
+/// Every block of the field, top left first and the garbage line last, as fumen block numbers.
+type Field = [u8; FIELD_BLOCKS as usize];
+
+/// One page of a fumen: a board, optionally a piece being placed on it, and a comment.
+#[derive(Clone)]
+pub struct Page {
+    pub board: Board,
+    /// The active piece and its origin in board coordinates.
+    pub piece: Option<(Piece, Point)>,
+    pub comment: String,
+    /// True if the piece locks, and any lines it completes clear, before the next page.
+    pub lock: bool,
+    /// The line below the floor, which `rise` pushes up into the board.
+    pub garbage: [Option<Color>; BOARD_WIDTH as usize],
+    /// True if every line moves up by one after the piece locks, the garbage line becoming the bottom line.
+    pub rise: bool,
+    /// True if the board is flipped left to right after the piece locks.
+    pub mirror: bool,
+}
+
+impl Page {
+    /// A page showing `board` without a piece or comment.
+    pub fn new(board: Board) -> Page {
+        Page {
+            board,
+            piece: None,
+            comment: String::new(),
+            lock: true,
+            garbage: [None; BOARD_WIDTH as usize],
+            rise: false,
+            mirror: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FumenError {
+    Version,
+    Character(char),
+    Truncated,
+    Invalid,
+    TooTall,
+}
+
+impl fmt::Display for FumenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FumenError::Version => write!(f, "not a v115 fumen"),
+            FumenError::Character(c) => write!(f, "unexpected character '{}' in fumen", c),
+            FumenError::Truncated => write!(f, "fumen ends unexpectedly"),
+            FumenError::Invalid => write!(f, "fumen contains an invalid field or piece"),
+            FumenError::TooTall => write!(f, "fumen field is taller than the board"),
+        }
+    }
+}
+
+/// Reads the base 64 digits of a fumen, lowest digit first.
+struct Reader {
+    digits: Vec<u32>,
+    position: usize,
+}
+
+impl Reader {
+    fn is_empty(&self) -> bool {
+        self.position >= self.digits.len()
+    }
+
+    fn poll(&mut self, count: u32) -> Result<u32, FumenError> {
+        let mut value = 0;
+        for i in 0..count {
+            let digit = *self
+                .digits
+                .get(self.position)
+                .ok_or(FumenError::Truncated)?;
+            value += digit * 64u32.pow(i);
+            self.position += 1;
+        }
+        Ok(value)
+    }
+}
+
+fn push(out: &mut String, mut value: u32, count: u32) {
+    for _ in 0..count {
+        out.push(ENCODE_TABLE[(value % 64) as usize] as char);
+        value /= 64;
+    }
+}
+
+/// Decodes every page of a fumen string such as `v115@vhAAgH`. The `?` separators and any line breaks a copy
+/// picked up are skipped.
+pub fn decode(fumen: &str) -> Result<Vec<Page>, FumenError> {
+    let data = match fumen.trim().get(..5) {
+        Some("v115@") | Some("m115@") | Some("d115@") => &fumen.trim()[5..],
+        _ => return Err(FumenError::Version),
+    };
+    let digits = data
+        .chars()
+        .filter(|&c| c != '?' && !c.is_whitespace())
+        .map(|c| {
+            ENCODE_TABLE
+                .iter()
+                .position(|&e| e as char == c)
+                .map(|v| v as u32)
+                .ok_or(FumenError::Character(c))
+        })
+        .collect::<Result<Vec<u32>, FumenError>>()?;
+    let mut reader = Reader {
+        digits,
+        position: 0,
+    };
+
+    let mut pages = Vec::new();
+    let mut field: Field = [0; FIELD_BLOCKS as usize];
+    let mut repeat = 0;
+    let mut comment = String::new();
+    while !reader.is_empty() {
+        if repeat > 0 {
+            repeat -= 1;
+        } else {
+            let mut index = 0;
+            while index < FIELD_BLOCKS {
+                let run = reader.poll(2)?;
+                let diff = (run / FIELD_BLOCKS) as i32 - 8;
+                let blocks = run % FIELD_BLOCKS + 1;
+                if diff == 0 && blocks == FIELD_BLOCKS {
+                    repeat = reader.poll(1)?;
+                }
+                for _ in 0..blocks {
+                    let cell = field.get_mut(index as usize).ok_or(FumenError::Invalid)?;
+                    let value = *cell as i32 + diff;
+                    if !(0..=GARBAGE as i32).contains(&value) {
+                        return Err(FumenError::Invalid);
+                    }
+                    *cell = value as u8;
+                    index += 1;
+                }
+            }
+        }
+
+        let mut action = reader.poll(3)?;
+        let kind = action % 8;
+        action /= 8;
+        let rotation = action % 4;
+        action /= 4;
+        let location = action % FIELD_BLOCKS;
+        action /= FIELD_BLOCKS;
+        let rise = action % 2 == 1;
+        let mirror = (action / 2) % 2 == 1;
+        let has_comment = (action / 8) % 2 == 1;
+        let lock = (action / 16) % 2 == 0;
+
+        if has_comment {
+            let length = reader.poll(2)? as usize;
+            let mut escaped = String::new();
+            for _ in 0..length.div_ceil(4) {
+                let mut value = reader.poll(5)?;
+                for _ in 0..4 {
+                    escaped.push((b' ' + (value % COMMENT_BASE) as u8) as char);
+                    value /= COMMENT_BASE;
+                }
+            }
+            escaped.truncate(length);
+            comment = unescape(&escaped);
+        }
+
+        let piece = match KINDS.get(kind as usize).copied().flatten() {
+            Some(kind) => Some(decode_piece(kind, rotation, location)?),
+            None => None,
+        };
+        let garbage = (FIELD_BLOCKS - BOARD_WIDTH) as usize;
+        let page = Page {
+            board: field_to_board(&field)?,
+            piece,
+            comment: comment.clone(),
+            lock,
+            garbage: std::array::from_fn(|x| color(field[garbage + x])),
+            rise,
+            mirror,
+        };
+        if !apply(&mut field, &page) {
+            return Err(FumenError::Invalid);
+        }
+        pages.push(page);
+    }
+    Ok(pages)
+}
+
+/// Locks the piece of `page` into `field` and applies the page's flags, if the piece locks. Returns false if
+/// part of the piece is outside the field.
+fn apply(field: &mut Field, page: &Page) -> bool {
+    if !page.lock {
+        return true;
+    }
+    let mut inside = true;
+    if let Some((piece, origin)) = &page.piece {
+        for cell in piece.cells(*origin) {
+            match board_index(cell) {
+                Some(index) => field[index] = block(piece.color),
+                None => inside = false,
+            }
+        }
+    }
+    clear_lines(field);
+    if page.rise {
+        // Every line moves up and the garbage line becomes the bottom line of the field.
+        field.copy_within(BOARD_WIDTH as usize.., 0);
+        field[(FIELD_BLOCKS - BOARD_WIDTH) as usize..].fill(0);
+    }
+    if page.mirror {
+        let play = (FIELD_BLOCKS - BOARD_WIDTH) as usize;
+        field[..play]
+            .chunks_mut(BOARD_WIDTH as usize)
+            .for_each(|row| row.reverse());
+    }
+    inside
+}
+
+/// Encodes `pages` as a fumen string.
+pub fn encode(pages: &[Page]) -> String {
+    let mut data = String::new();
+    let mut field: Field = [0; FIELD_BLOCKS as usize];
+    // Position of the counter of pages that repeat the previous field, while such a run is ongoing.
+    let mut repeat: Option<(usize, u32)> = None;
+    let mut comment = "";
+
+    for (i, page) in pages.iter().enumerate() {
+        let mut next = field;
+        let play = (FIELD_BLOCKS - BOARD_WIDTH) as usize;
+        next[(FIELD_TOP - BOARD_HEIGHT) as usize * BOARD_WIDTH as usize..play]
+            .iter_mut()
+            .zip(page.board.cells.iter().flatten())
+            .for_each(|(block_number, cell)| *block_number = cell.map_or(0, block));
+        next[play..]
+            .iter_mut()
+            .zip(page.garbage.iter())
+            .for_each(|(block_number, cell)| *block_number = cell.map_or(0, block));
+
+        let mut runs = Vec::new();
+        for (new, old) in next.iter().zip(field.iter()) {
+            let diff = (*new as i32 - *old as i32 + 8) as u32;
+            match runs.last_mut() {
+                Some((d, count)) if *d == diff => *count += 1,
+                _ => runs.push((diff, 1)),
+            }
+        }
+        if runs.len() == 1 && runs[0].0 == 8 {
+            match repeat {
+                Some((at, count)) if count < 63 => {
+                    repeat = Some((at, count + 1));
+                    data.replace_range(
+                        at..=at,
+                        &(ENCODE_TABLE[count as usize + 1] as char).to_string(),
+                    );
+                }
+                _ => {
+                    push(&mut data, 8 * FIELD_BLOCKS + FIELD_BLOCKS - 1, 2);
+                    repeat = Some((data.len(), 0));
+                    push(&mut data, 0, 1);
+                }
+            }
+        } else {
+            repeat = None;
+            for (diff, count) in runs {
+                push(&mut data, diff * FIELD_BLOCKS + count - 1, 2);
+            }
+        }
+        field = next;
+
+        let (kind, rotation, location) = page
+            .piece
+            .as_ref()
+            .and_then(|(piece, origin)| encode_piece(piece, *origin))
+            .unwrap_or((0, REVERSE, 0));
+        let has_comment = page.comment != comment;
+        let mut action = if page.lock { 0 } else { 1 };
+        action = action * 2 + has_comment as u32;
+        action = action * 2 + (i == 0) as u32;
+        action = action * 2 + page.mirror as u32;
+        action = action * 2 + page.rise as u32;
+        action = action * FIELD_BLOCKS + location;
+        action = action * 4 + rotation;
+        action = action * 8 + kind;
+        push(&mut data, action, 3);
+
+        if has_comment {
+            let escaped = escape(&page.comment);
+            let escaped = &escaped.as_bytes()[..escaped.len().min(4095)];
+            push(&mut data, escaped.len() as u32, 2);
+            for chunk in escaped.chunks(4) {
+                let value = chunk
+                    .iter()
+                    .rev()
+                    .fold(0, |v, &c| v * COMMENT_BASE + (c - b' ') as u32);
+                push(&mut data, value, 5);
+            }
+            comment = &page.comment;
+        }
+
+        apply(&mut field, page);
+    }
+
+    // Fumen strings are broken up with question marks so that they wrap in chat.
+    let mut fumen = String::from("v115@");
+    if data.len() <= 42 {
+        fumen.push_str(&data);
+    } else {
+        fumen.push_str(&data[..42]);
+        for chunk in data.as_bytes()[42..].chunks(47) {
+            fumen.push('?');
+            fumen.push_str(std::str::from_utf8(chunk).unwrap());
+        }
+    }
+    fumen
+}
+
+/// Encodes a single board without an active piece.
+pub fn encode_board(board: &Board) -> String {
+    encode(&[Page::new(board.clone())])
+}
+
+fn block(color: Color) -> u8 {
+    KINDS
+        .iter()
+        .position(|k| k.is_some_and(|k| Piece::new(k).color == color))
+        .map_or(GARBAGE, |b| b as u8)
+}
+
+fn color(block: u8) -> Option<Color> {
+    match KINDS.get(block as usize) {
+        Some(Some(kind)) => Some(Piece::new(*kind).color),
+        Some(None) => None,
+        None => Some(Color::Gray),
+    }
+}
+
+/// Returns the index of a board cell in the field.
+fn board_index(cell: Point) -> Option<usize> {
+    let row = cell.y + (FIELD_TOP - BOARD_HEIGHT) as i32;
+    if cell.x < 0 || cell.x >= BOARD_WIDTH as i32 || row < 0 || row >= FIELD_TOP as i32 {
+        return None;
+    }
+    Some((row * BOARD_WIDTH as i32 + cell.x) as usize)
+}
+
+fn field_to_board(field: &Field) -> Result<Board, FumenError> {
+    let top = ((FIELD_TOP - BOARD_HEIGHT) * BOARD_WIDTH) as usize;
+    if field[..top].iter().any(|&b| b != 0) {
+        return Err(FumenError::TooTall);
+    }
+    let mut board = Board::new();
+    for (cell, &b) in board.cells.iter_mut().flatten().zip(field[top..].iter()) {
+        *cell = color(b);
+    }
+    Ok(board)
+}
+
+/// Removes the full lines above the garbage line, shifting the lines above them down.
+fn clear_lines(field: &mut Field) {
+    let width = BOARD_WIDTH as usize;
+    let play = (FIELD_BLOCKS - BOARD_WIDTH) as usize;
+    let rows: Vec<Vec<u8>> = field[..play]
+        .chunks(width)
+        .filter(|row| row.contains(&0))
+        .map(|row| row.to_vec())
+        .collect();
+    let start = play - rows.len() * width;
+    field[..start].fill(0);
+    for (i, row) in rows.iter().enumerate() {
+        field[start + i * width..start + (i + 1) * width].copy_from_slice(row);
+    }
+}
+
+/// The cells of a piece relative to its rotation centre, with y pointing up.
+fn offsets(kind: PieceKind, rotation: u32) -> Vec<(i32, i32)> {
+    let spawn: [(i32, i32); 4] = match kind {
+        PieceKind::I => [(0, 0), (-1, 0), (1, 0), (2, 0)],
+        PieceKind::T => [(0, 0), (-1, 0), (1, 0), (0, 1)],
+        PieceKind::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        PieceKind::L => [(0, 0), (-1, 0), (1, 0), (1, 1)],
+        PieceKind::J => [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+        PieceKind::S => [(0, 0), (-1, 0), (0, 1), (1, 1)],
+        PieceKind::Z => [(0, 0), (1, 0), (0, 1), (-1, 1)],
+    };
+    spawn
+        .iter()
+        .map(|&(x, y)| match rotation {
+            RIGHT => (y, -x),
+            REVERSE => (-x, -y),
+            LEFT => (-y, x),
+            _ => (x, y),
+        })
+        .collect()
+}
+
+/// Fumen stores some pieces one cell off from their rotation centre. Returns the correction to apply when
+/// decoding.
+fn centre_shift(kind: PieceKind, rotation: u32) -> (i32, i32) {
+    match (kind, rotation) {
+        (PieceKind::O, LEFT) => (1, -1),
+        (PieceKind::O, REVERSE) => (1, 0),
+        (PieceKind::O, SPAWN) => (0, -1),
+        (PieceKind::I, REVERSE) => (1, 0),
+        (PieceKind::I, LEFT) => (0, -1),
+        (PieceKind::S, SPAWN) => (0, -1),
+        (PieceKind::S, RIGHT) => (-1, 0),
+        (PieceKind::Z, SPAWN) => (0, -1),
+        (PieceKind::Z, LEFT) => (1, 0),
+        _ => (0, 0),
+    }
+}
+
+fn sorted(mut cells: Vec<Point>) -> Vec<Point> {
+    cells.sort_by_key(|p| (p.y, p.x));
+    cells
+}
+
+fn decode_piece(
+    kind: PieceKind,
+    rotation: u32,
+    location: u32,
+) -> Result<(Piece, Point), FumenError> {
+    let (dx, dy) = centre_shift(kind, rotation);
+    let x = (location % BOARD_WIDTH) as i32 + dx;
+    let y = (FIELD_TOP - 1 - location / BOARD_WIDTH) as i32 + dy;
+    let target = sorted(
+        offsets(kind, rotation)
+            .iter()
+            .map(|&(ox, oy)| Point {
+                x: x + ox,
+                y: BOARD_HEIGHT as i32 - 1 - (y + oy),
+            })
+            .collect(),
+    );
+
+    let mut piece = Piece::new(kind);
+    for _ in 0..4 {
+        let cells = sorted(piece.cells(Point { x: 0, y: 0 }));
+        let origin = Point {
+            x: target[0].x - cells[0].x,
+            y: target[0].y - cells[0].y,
+        };
+        if sorted(piece.cells(origin)) == target {
+            return Ok((piece, origin));
+        }
+        piece.rotate(Direction::Right);
+    }
+    Err(FumenError::Invalid)
+}
+
+/// Returns the fumen piece number, rotation and location of `piece` at `origin`. The I, O, S and Z pieces
+/// cover the same cells in two or four rotations; they are written in the first of spawn, right, reverse and
+/// left that fits, as the fumen editor places them.
+fn encode_piece(piece: &Piece, origin: Point) -> Option<(u32, u32, u32)> {
+    let kind = KINDS.iter().position(|&k| k == Some(piece.kind))? as u32;
+    let cells = sorted(piece.cells(origin));
+    for rotation in [SPAWN, RIGHT, REVERSE, LEFT] {
+        let shape = sorted(
+            offsets(piece.kind, rotation)
+                .iter()
+                .map(|&(x, y)| Point { x, y: -y })
+                .collect(),
+        );
+        let centre = Point {
+            x: cells[0].x - shape[0].x,
+            y: cells[0].y - shape[0].y,
+        };
+        if shape
+            .iter()
+            .map(|p| Point {
+                x: centre.x + p.x,
+                y: centre.y + p.y,
+            })
+            .eq(cells.iter().copied())
+        {
+            let (dx, dy) = centre_shift(piece.kind, rotation);
+            let x = centre.x - dx;
+            let y = BOARD_HEIGHT as i32 - 1 - centre.y - dy;
+            let row = FIELD_TOP as i32 - 1 - y;
+            if x < 0 || x >= BOARD_WIDTH as i32 || row < 0 || row >= (FIELD_TOP + 1) as i32 {
+                return None;
+            }
+            return Some((kind, rotation, (row * BOARD_WIDTH as i32 + x) as u32));
+        }
+    }
+    None
+}
+
+/// Percent-encodes a comment the way JavaScript's `escape` does, which is what fumen stores.
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for unit in text.encode_utf16() {
+        match char::from_u32(unit as u32) {
+            Some(c) if c.is_ascii_alphanumeric() || "@*_+-./".contains(c) => escaped.push(c),
+            _ if unit < 256 => escaped.push_str(&format!("%{:02X}", unit)),
+            _ => escaped.push_str(&format!("%u{:04X}", unit)),
+        }
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut units = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = |from: usize, len: usize| {
+            text.get(from..from + len)
+                .and_then(|h| u16::from_str_radix(h, 16).ok())
+        };
+        if bytes[i] == b'%' {
+            if bytes.get(i + 1) == Some(&b'u') {
+                if let Some(unit) = hex(i + 2, 4) {
+                    units.push(unit);
+                    i += 6;
+                    continue;
+                }
+            } else if let Some(unit) = hex(i + 1, 2) {
+                units.push(unit);
+                i += 3;
+                continue;
+            }
+        }
+        units.push(bytes[i] as u16);
+        i += 1;
+    }
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg32;
+
+    const SINGLE: &str = "v115@RhB8DeG8BeE8JeFLJ";
+    const PAGES: &str = "v115@vhDRQYFAIoMDEPBAAAyuBWyQHAMOprDFrmAAAAA";
+    const FLAGS: &str = "v115@bhF8BeC8AeH8AINvhAVlf";
+    const LONG: &str =
+        "v115@9gD8AeI8AeI8AeI8AeE8JeJGYeAGHxXEFbEBEJGlaE?FLHSASY91DFG98Awx88AQ/AAAvhB2uBAAA";
+
+    /// The colour of a cell written as in puzzle files: a piece letter, `.` for empty or `X` for garbage.
+    fn cell(c: char) -> Option<Color> {
+        match c {
+            '.' => None,
+            'X' => Some(Color::Gray),
+            _ => KINDS
+                .iter()
+                .flatten()
+                .find(|k| format!("{:?}", k) == c.to_string())
+                .map(|&k| Piece::new(k).color),
+        }
+    }
+
+    /// A board whose bottom lines are `lines`, the top one first.
+    fn board(lines: &[&str]) -> Board {
+        let mut board = Board::new();
+        let top = BOARD_HEIGHT as usize - lines.len();
+        for (y, line) in lines.iter().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                board.cells[top + y][x] = cell(c);
+            }
+        }
+        board
+    }
+
+    /// The kind of a page's piece and the cells it covers, bottom line 0 and counting up.
+    fn piece(page: &Page) -> Option<(PieceKind, Vec<(i32, i32)>)> {
+        page.piece.as_ref().map(|(piece, origin)| {
+            let mut cells: Vec<(i32, i32)> = piece
+                .cells(*origin)
+                .iter()
+                .map(|p| (p.x, BOARD_HEIGHT as i32 - 1 - p.y))
+                .collect();
+            cells.sort();
+            (piece.kind, cells)
+        })
+    }
+
+    fn assert_same(decoded: &Page, page: &Page) {
+        assert_eq!(decoded.board.cells, page.board.cells);
+        assert_eq!(piece(decoded), piece(page));
+        assert_eq!(decoded.comment, page.comment);
+        assert_eq!(decoded.lock, page.lock);
+        assert_eq!(decoded.garbage, page.garbage);
+        assert_eq!(decoded.rise, page.rise);
+        assert_eq!(decoded.mirror, page.mirror);
+    }
+
+    #[test]
+    fn single_page() {
+        let pages = decode(SINGLE).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(
+            pages[0].board.cells,
+            board(&["XX....XXXX", "XXX..XXXXX"]).cells
+        );
+        assert_eq!(
+            piece(&pages[0]),
+            Some((PieceKind::T, vec![(3, 1), (4, 0), (4, 1), (5, 1)]))
+        );
+        assert_eq!(pages[0].comment, "");
+        assert_eq!(encode(&pages), SINGLE);
+    }
+
+    #[test]
+    fn pages_and_comments() {
+        let pages = decode(PAGES).unwrap();
+        assert_eq!(pages.len(), 4);
+        let comments: Vec<&str> = pages.iter().map(|p| p.comment.as_str()).collect();
+        assert_eq!(comments, ["Hello", "Hello", "Line!", "Line!"]);
+        assert_eq!(pages[1].board.cells, board(&["...IIII..."]).cells);
+        assert_eq!(
+            pages[2].board.cells,
+            board(&["..L.......", "LLLIIII..."]).cells
+        );
+        // The J completes the bottom line, which is cleared before the last page.
+        assert_eq!(pages[3].board.cells, board(&["..L....J.."]).cells);
+        assert!(pages[3].piece.is_none());
+        assert_eq!(encode(&pages), PAGES);
+    }
+
+    #[test]
+    fn rise_and_mirror() {
+        let pages = decode(FLAGS).unwrap();
+        assert!(pages[0].rise && pages[0].mirror);
+        assert_eq!(pages[0].garbage, board(&["X.XXXXXXXX"]).cells[19]);
+        // The garbage line rises into the board, then the board is flipped.
+        assert_eq!(
+            pages[1].board.cells,
+            board(&["XX..XXXXXX", "XXXXXXXX.X"]).cells
+        );
+        assert!(!pages[1].lock);
+        assert_eq!(encode(&pages), FLAGS);
+    }
+
+    #[test]
+    fn long_fumen() {
+        let pages = decode(LONG).unwrap();
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0].comment, "Four lines, then a J");
+        assert_eq!(
+            piece(&pages[0]),
+            Some((PieceKind::I, vec![(4, 0), (4, 1), (4, 2), (4, 3)]))
+        );
+        assert_eq!(
+            pages[2].board.cells,
+            board(&["J.........", "JJJ......."]).cells
+        );
+        assert_eq!(encode(&pages), LONG);
+    }
+
+    #[test]
+    fn splits_after_42_characters() {
+        let mut lengths = Vec::new();
+        for blocks in 0..BOARD_WIDTH as usize {
+            // Every other cell of the bottom two lines, so that each block adds two runs to the field.
+            let cells: Vec<char> = (0..BOARD_WIDTH as usize * 2)
+                .map(|i| {
+                    if i % 2 == 0 && i < blocks * 2 {
+                        'X'
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            let (top, bottom) = cells.split_at(BOARD_WIDTH as usize);
+            let lines = [top.iter().collect::<String>(), bottom.iter().collect()];
+            for comment in 0..16 {
+                let page = Page {
+                    comment: "x".repeat(comment),
+                    ..Page::new(board(&[&lines[0], &lines[1]]))
+                };
+                let fumen = encode(&[page]);
+                let data = fumen.len() - "v115@".len() - fumen.matches('?').count();
+                assert_eq!(fumen.contains('?'), data > 42, "{}", fumen);
+                assert_eq!(encode(&decode(&fumen).unwrap()), fumen);
+                lengths.push(data);
+            }
+        }
+        assert!(lengths.contains(&41) && lengths.contains(&42) && lengths.contains(&43));
+    }
+
+    #[test]
+    fn random_boards_survive_encoding() {
+        let mut rng = Pcg32::seed_from_u64(115);
+        let kinds: Vec<PieceKind> = KINDS.iter().flatten().copied().collect();
+        for _ in 0..200 {
+            let pages: Vec<Page> = (0..rng.gen_range(1, 5))
+                .map(|_| {
+                    let mut page = Page::new(Board::new());
+                    let density = rng.gen_range(0.0, 1.0);
+                    for cell in page.board.cells.iter_mut().flatten() {
+                        if rng.gen_bool(density) {
+                            *cell = Some(match rng.gen_range(0, 8) {
+                                7 => Color::Gray,
+                                k => Piece::new(kinds[k]).color,
+                            });
+                        }
+                    }
+                    if rng.gen_bool(0.5) {
+                        let mut piece = Piece::new(kinds[rng.gen_range(0, 7)]);
+                        for _ in 0..rng.gen_range(0, 4) {
+                            piece.rotate(Direction::Right);
+                        }
+                        let origin = Point {
+                            x: rng.gen_range(0, BOARD_WIDTH as i32 - 3),
+                            y: rng.gen_range(0, BOARD_HEIGHT as i32 - 3),
+                        };
+                        page.piece = Some((piece, origin));
+                    }
+                    page.comment = (0..rng.gen_range(0, 12))
+                        .map(|_| rng.gen_range(b' ', b'~') as char)
+                        .collect();
+                    page.lock = rng.gen_bool(0.5);
+                    page
+                })
+                .collect();
+
+            let fumen = encode(&pages);
+            let decoded = decode(&fumen).unwrap();
+            assert_eq!(decoded.len(), pages.len());
+            for (decoded, page) in decoded.iter().zip(pages.iter()) {
+                assert_same(decoded, page);
+            }
+        }
+    }
+}
@@ -0,0 +1,1209 @@
+use crate::ai::{Bot, Placement};
+use crate::board::*;
+use crate::effects::{self, Effect};
+use crate::finesse::FinesseTracker;
+use crate::fumen::Page;
+use crate::hud::Hud;
+use crate::input;
+use crate::keymap::{Action, Key, Keymap};
+use crate::opener::{OpenerTrainer, OPENERS};
+use crate::piece::*;
+use crate::puzzle::{Puzzle, PuzzleState};
+use crate::render::Renderer;
+use crate::rules::Rules;
+use crate::settings::Settings;
+use crate::snapshot::{self, Snapshot};
+use crate::solver;
+use crate::stats::Stats;
+use crate::util::*;
+use signal_hook::consts::SIGWINCH;
+use signal_hook::iterator::Signals;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+enum GameUpdate {
+    KeyPress(Key),
+    Tick,
+    /// The terminal was resized, so the game has to be drawn again.
+    Resize,
+    /// The entry delay is over, so the next piece comes in.
+    Spawn,
+    /// An effect needs its next frame drawn.
+    Frame,
+}
+
+/// Undo keeps at least this many placements.
+const UNDO_LIMIT: usize = 200;
+
+/// How often the input thread checks whether the game is over while no key is pressed.
+const INPUT_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Mode {
+    Marathon,
+    /// Shows the optimal inputs after every finesse fault.
+    Finesse,
+    /// Drills the opener at this index of `OPENERS`, restarting on every misplaced piece.
+    Opener(usize),
+    /// Plays a puzzle loaded with `Game::with_puzzle`.
+    Puzzle,
+}
+
+impl Mode {
+    /// The modes with a leaderboard.
+    pub const SCORED: [Mode; 2] = [Mode::Marathon, Mode::Finesse];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Marathon => "marathon",
+            Mode::Finesse => "finesse",
+            Mode::Opener(_) => "opener",
+            Mode::Puzzle => "puzzle",
+        }
+    }
+
+    /// Practice modes allow undo.
+    pub fn is_practice(self) -> bool {
+        self != Mode::Marathon
+    }
+}
+
+/// Rotations and holds pressed during the entry delay, applied to the next piece as it comes in.
+#[derive(Default)]
+struct Buffered {
+    rotation: Option<Direction>,
+    hold: bool,
+}
+
+/// The parts of a game that undo and redo restore.
+#[derive(Clone)]
+struct UndoState {
+    board: Board,
+    piece_bag: PieceBag,
+    piece: Piece,
+    hold: Option<Piece>,
+    score: u32,
+    lines: u32,
+    level: u32,
+    to_clear: i32,
+    speed: u64,
+    cleared_last_round: u32,
+    combo_counter: u32,
+    history: usize,
+    opener: Option<OpenerTrainer>,
+    puzzle: Option<PuzzleState>,
+    stats: Stats,
+    finesse: FinesseTracker,
+    hints_used: u32,
+}
+
+pub struct Game {
+    pub mode: Mode,
+    pub board: Board,
+    piece_bag: PieceBag,
+    pub piece: Piece,
+    hold: Option<Piece>,
+    pub piece_position: Point,
+    pub score: u32,
+    pub lines: u32,
+    switched: bool,
+    pub level: u32,
+    speed: Arc<AtomicU64>,
+    to_clear: i32,
+    paused: Arc<AtomicBool>,
+    cleared_last_round: u32,
+    combo_counter: u32,
+    /// Loaded the first time hints are turned on.
+    bot: Option<Bot>,
+    show_hints: bool,
+    hint: Option<Placement>,
+    pub hints_used: u32,
+    /// Every piece locked so far, with the board it locked on, for exporting the game as a fumen.
+    pub history: Vec<Page>,
+    pub finesse: FinesseTracker,
+    pub seed: u64,
+    opener: Option<OpenerTrainer>,
+    pc_chance: Option<f64>,
+    pub puzzle: Option<PuzzleState>,
+    /// True if the last successful move of the current piece was a rotation.
+    last_rotated: bool,
+    /// True if the player quit rather than topping out.
+    pub quit: bool,
+    /// The state when the current piece spawned, before it was held or moved.
+    spawn_state: Option<UndoState>,
+    undo: VecDeque<UndoState>,
+    redo: Vec<UndoState>,
+    /// Games where undo was used are not eligible for the leaderboard.
+    pub undos_used: u32,
+    pub stats: Stats,
+    pub hud: Hud,
+    pub keymap: Keymap,
+    pub rules: Rules,
+    /// When the game was last started or unpaused, while it is running.
+    clock: Option<Instant>,
+    /// When the next piece comes in, during the entry delay. The current piece has locked by then.
+    spawn_at: Option<Instant>,
+    buffered: Buffered,
+    /// Whether locking pieces and clearing lines is animated.
+    pub animations: bool,
+    effect: Option<Effect>,
+}
+
+impl Game {
+    pub fn new(mode: Mode) -> Game {
+        Game::with_seed(mode, rand::random())
+    }
+
+    /// Creates a game whose piece sequence is fully determined by `seed`.
+    pub fn with_seed(mode: Mode, seed: u64) -> Game {
+        let mut piece_bag = match mode {
+            Mode::Opener(i) => PieceBag::with_queue(seed, OPENERS[i].queue),
+            _ => PieceBag::with_seed(seed),
+        };
+        let piece = piece_bag.pop();
+
+        let mut game = Game {
+            mode,
+            board: Board::new(),
+            piece_bag,
+            piece,
+            hold: None,
+            piece_position: Point { x: 0, y: 0 },
+            score: 0,
+            lines: 0,
+            switched: false,
+            level: 1,
+            speed: Arc::new(AtomicU64::new(500)),
+            to_clear: 10,
+            paused: Arc::new(AtomicBool::new(false)),
+            cleared_last_round: 0,
+            combo_counter: 0,
+            bot: None,
+            show_hints: false,
+            hint: None,
+            hints_used: 0,
+            finesse: FinesseTracker::new(),
+            seed,
+            opener: match mode {
+                Mode::Opener(i) => Some(OpenerTrainer::new(&OPENERS[i])),
+                _ => None,
+            },
+            pc_chance: None,
+            history: Vec::new(),
+            puzzle: None,
+            last_rotated: false,
+            quit: false,
+            spawn_state: None,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            undos_used: 0,
+            stats: Stats::default(),
+            hud: Hud::default(),
+            keymap: Keymap::default(),
+            rules: Rules::default(),
+            clock: None,
+            spawn_at: None,
+            buffered: Buffered::default(),
+            animations: true,
+            effect: None,
+        };
+
+        game.place_new_piece();
+        game
+    }
+
+    /// Creates a game that plays `puzzle` until its goal is reached or its pieces run out.
+    pub fn with_puzzle(puzzle: Puzzle) -> Game {
+        let mut game = Game::with_seed(Mode::Puzzle, rand::random());
+        game.puzzle = Some(PuzzleState::new(puzzle));
+        game.restart();
+        game
+    }
+
+    /// Starts the game at `level`, with the speed and the lines to clear of that level.
+    pub fn set_level(&mut self, level: u32) {
+        self.level = level;
+        self.to_clear = level as i32 * 10;
+        self.speed
+            .store(500 - (level as u64 - 1) * 10, Ordering::SeqCst);
+    }
+
+    /// Continues a marathon game saved with `snapshot`.
+    pub fn from_snapshot(snapshot: Snapshot) -> Game {
+        let mut game = Game::with_seed(Mode::Marathon, snapshot.seed);
+        game.board = snapshot.board;
+        game.piece = Piece::new(snapshot.piece);
+        game.piece.shape = snapshot.shape;
+        game.piece_position = snapshot.position;
+        game.hold = snapshot.hold.map(Piece::new);
+        game.switched = snapshot.switched;
+        game.piece_bag = PieceBag::from_state(snapshot.bag);
+        game.score = snapshot.score;
+        game.lines = snapshot.lines;
+        game.level = snapshot.level;
+        game.to_clear = snapshot.to_clear;
+        game.speed.store(snapshot.speed, Ordering::SeqCst);
+        game.combo_counter = snapshot.combo;
+        game.cleared_last_round = snapshot.cleared_last_round;
+        game.hints_used = snapshot.hints_used;
+        game.stats = snapshot.stats;
+        game.rules = snapshot.rules;
+        game.finesse.spawned(&game.piece, game.piece_position);
+        game
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: snapshot::VERSION,
+            board: self.board.clone(),
+            piece: self.piece.kind,
+            shape: self.piece.shape.clone(),
+            position: self.piece_position,
+            hold: self.hold.as_ref().map(|p| p.kind),
+            switched: self.switched,
+            bag: self.piece_bag.state(),
+            seed: self.seed,
+            score: self.score,
+            lines: self.lines,
+            level: self.level,
+            to_clear: self.to_clear,
+            speed: self.speed.load(Ordering::SeqCst),
+            combo: self.combo_counter,
+            cleared_last_round: self.cleared_last_round,
+            hints_used: self.hints_used,
+            stats: self.stats.clone(),
+            rules: self.rules.clone(),
+        }
+    }
+
+    /// Returns the new position of the current piece if it were to be dropped.
+    pub fn find_dropped_position(&self) -> Point {
+        let mut origin = self.piece_position;
+        while !self.board.collision_test(&self.piece, origin) {
+            origin.y += 1;
+        }
+        origin.y -= 1;
+        origin
+    }
+
+    /// Draws the game to the display.
+    pub fn render(&self, display: &mut impl Renderer) {
+        // Render the board, animated if a piece just locked
+        match &self.effect {
+            Some(effect) => effect.render(&self.board, display),
+            None => self.board.render(display),
+        }
+
+        // Render the level
+        let left_margin = BOARD_WIDTH * 2 + 5;
+        display.set_text(
+            format!("Level: {}", self.level),
+            left_margin,
+            3,
+            Color::Text,
+            Color::Black,
+        );
+
+        //render score
+        display.set_text(
+            format!("Score: {}", self.score),
+            left_margin,
+            5,
+            Color::Text,
+            Color::Black,
+        );
+
+        //render combo
+        display.set_text(
+            format!("Combo: {}", self.combo_counter),
+            left_margin,
+            7,
+            Color::Text,
+            Color::Black,
+        );
+        // Render the enabled HUD fields in a second column
+        let fields = self.hud.fields(&self.stats, self.elapsed(), self.lines);
+        for (i, field) in fields.iter().enumerate() {
+            display.set_text(
+                field,
+                left_margin + 20,
+                3 + i as u32,
+                Color::Text,
+                Color::Black,
+            );
+        }
+
+        if self.show_hints {
+            display.set_text(
+                format!("Hints: {}", self.hints_used),
+                left_margin,
+                18,
+                Color::Text,
+                Color::Black,
+            );
+        }
+
+        if self.mode == Mode::Finesse {
+            display.set_text(
+                format!(
+                    "Faults: {}/{}",
+                    self.finesse.total_faults(),
+                    self.finesse.total_pieces()
+                ),
+                left_margin,
+                19,
+                Color::Text,
+                Color::Black,
+            );
+            if let Some(optimal) = &self.finesse.last_fault {
+                let inputs: Vec<&str> = optimal.iter().map(|i| i.name()).collect();
+                display.set_text(
+                    format!("Optimal: {}", inputs.join(" ")),
+                    left_margin,
+                    20,
+                    Color::Highlight,
+                    Color::Black,
+                );
+            }
+        }
+
+        if let Some(trainer) = &self.opener {
+            display.set_text(
+                format!(
+                    "{}: {}/{}",
+                    trainer.opener.name, trainer.completed, trainer.attempts
+                ),
+                left_margin,
+                19,
+                Color::Text,
+                Color::Black,
+            );
+            let chance = match self.pc_chance {
+                Some(c) => format!("{:.0}%", c * 100.0),
+                None => "-".to_string(),
+            };
+            display.set_text(
+                format!("PC chance: {}", chance),
+                left_margin,
+                20,
+                Color::Text,
+                Color::Black,
+            );
+
+            // Outline the cells the rest of the opener still has to fill
+            for (kind, cells) in trainer.remaining() {
+                let color = Piece::new(*kind).color;
+                for cell in cells {
+                    draw_outline(display, *cell, color);
+                }
+            }
+        }
+
+        if let Some(state) = &self.puzzle {
+            display.set_text(
+                &state.puzzle.name,
+                left_margin,
+                19,
+                Color::Text,
+                Color::Black,
+            );
+            display.set_text(
+                format!("Goal: {}", state.puzzle.goal.describe()),
+                left_margin,
+                20,
+                Color::Text,
+                Color::Black,
+            );
+            display.set_text(
+                format!("Pieces left: {}", state.pieces_left),
+                left_margin,
+                21,
+                Color::Text,
+                Color::Black,
+            );
+        }
+
+        // During the entry delay there's no piece to draw
+        if self.spawn_at.is_none() {
+            // Render the bot's suggested placement as an outline
+            if let Some(hint) = &self.hint {
+                for cell in hint.piece.cells(hint.position) {
+                    draw_outline(display, cell, hint.piece.color);
+                }
+            }
+
+            // Render a ghost piece, then the currently falling piece
+            let ghost_position = self.find_dropped_position();
+            for cell in self.piece.cells(ghost_position) {
+                draw_cell(display, cell, self.piece.get_shadow_color());
+            }
+            for cell in self.piece.cells(self.piece_position) {
+                draw_cell(display, cell, self.piece.color);
+            }
+        }
+
+        // Render the next piece
+        display.set_text("Next piece:", left_margin, 9, Color::Text, Color::Black);
+        if let Some(next_piece) = self.piece_bag.peek() {
+            self.render_piece(
+                display,
+                &next_piece,
+                Point {
+                    x: (left_margin as i32) + 2,
+                    y: 11,
+                },
+            );
+        }
+
+        // Render hold piece
+        display.set_text("Holding:", left_margin, 13, Color::Text, Color::Black);
+        if let Some(p) = &self.hold {
+            self.render_piece(
+                display,
+                p,
+                Point {
+                    x: (left_margin as i32) + 2,
+                    y: 15,
+                },
+            );
+        }
+    }
+
+    /// Renders a piece outside the playfield, like the next and held pieces.
+    fn render_piece(&self, display: &mut impl Renderer, piece: &Piece, origin: Point) {
+        piece.each_point(&mut |row, col| {
+            let x = (origin.x + 2 * col) as u32;
+            let y = (origin.y + row) as u32;
+            display.set_cell(x, y, piece.color);
+        });
+    }
+
+    /// Moves the current piece in the specified direction. Returns true if the piece could be moved and
+    /// didn't collide.
+    pub fn move_piece(&mut self, x: i32, y: i32) -> bool {
+        let new_position = Point {
+            x: self.piece_position.x + x,
+            y: self.piece_position.y + y,
+        };
+        if self.board.collision_test(&self.piece, new_position) {
+            false
+        } else {
+            self.piece_position = new_position;
+            self.last_rotated = false;
+            true
+        }
+    }
+
+    /// Rotates the current piece in the specified direction. Returns true if the piece could be rotated
+    /// without any collisions.
+    pub fn rotate_piece(&mut self, direction: Direction) -> bool {
+        match self
+            .board
+            .rotate_piece(&self.piece, self.piece_position, direction)
+        {
+            Some((piece, position)) => {
+                self.piece = piece;
+                self.piece_position = position;
+                self.last_rotated = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the current piece to its ghost position without locking it.
+    fn sonic_drop(&mut self) -> bool {
+        let dropped = self.find_dropped_position();
+        if dropped == self.piece_position {
+            return false;
+        }
+        self.piece_position = dropped;
+        self.last_rotated = false;
+        true
+    }
+
+    /// Switches the current piece with the held piece
+    /// Places a new piece when hold was empty previously, unless a puzzle's queue has run out
+    pub fn switch_hold(&mut self) -> bool {
+        self.swap_hold() && self.place_new_piece()
+    }
+
+    /// Puts the current piece on hold and takes the held piece, or the next one if none was held, without
+    /// placing it. Returns false if the piece was already swapped or a puzzle's queue has run out.
+    fn swap_hold(&mut self) -> bool {
+        if self.switched || (self.hold.is_none() && self.piece_bag.peek().is_none()) {
+            return false;
+        }
+        self.piece = match self.hold.replace(self.piece.clone()) {
+            Some(held) => held,
+            None => self.piece_bag.pop(),
+        };
+        self.switched = true;
+        true
+    }
+
+    ///Pauses or unpauses the game
+    fn pause(&mut self) -> bool {
+        let p = self.paused.load(Ordering::SeqCst);
+        self.paused.store(!p, Ordering::SeqCst);
+        if p {
+            self.clock = Some(Instant::now());
+        } else {
+            self.stop_clock();
+        }
+        true
+    }
+
+    /// Seconds played so far, not counting pauses.
+    fn elapsed(&self) -> f64 {
+        self.stats.duration
+            + self
+                .clock
+                .map_or(0.0, |start| start.elapsed().as_secs_f64())
+    }
+
+    /// Adds the time since the clock was started to the game's duration.
+    fn stop_clock(&mut self) {
+        if let Some(start) = self.clock.take() {
+            self.stats.duration += start.elapsed().as_secs_f64();
+        }
+    }
+
+    /// Positions the current piece at the top of the board, ending the entry delay if there is one.
+    /// Returns true if the piece can be placed without any collisions.
+    fn place_new_piece(&mut self) -> bool {
+        self.spawn_at = None;
+        self.buffered = Buffered::default();
+        let origin = Point {
+            x: ((BOARD_WIDTH - (self.piece.shape.len() as u32)) / 2) as i32,
+            y: 0,
+        };
+        if self.board.collision_test(&self.piece, origin) {
+            false
+        } else {
+            self.piece_position = origin;
+            self.last_rotated = false;
+            if self.mode.is_practice() && !self.switched {
+                self.spawn_state = Some(self.undo_state());
+            }
+            self.finesse.spawned(&self.piece, origin);
+            self.update_hint();
+            if let (Some(_), Some(next)) = (&self.opener, self.piece_bag.peek()) {
+                self.pc_chance = solver::pc_probability(
+                    &self.board,
+                    self.piece.kind,
+                    self.hold.as_ref().map(|p| p.kind),
+                    next.kind,
+                    &self.piece_bag.remaining(),
+                    self.piece_bag.known_order(),
+                );
+            }
+            true
+        }
+    }
+
+    /// Brings in the next piece, swapped for the held piece and rotated as buffered during the entry delay. A
+    /// hold or rotation that isn't possible is dropped. Once a puzzle's queue has run out, the held piece
+    /// comes in. Returns false if the piece doesn't fit or there is no piece left.
+    fn spawn_next_piece(&mut self) -> bool {
+        let buffered = std::mem::take(&mut self.buffered);
+        self.piece = match self.piece_bag.peek() {
+            Some(_) => self.piece_bag.pop(),
+            // A puzzle's queue has run out, which leaves the held piece as the last one
+            None => match self.hold.take() {
+                Some(held) => held,
+                None => {
+                    if let Some(state) = self.puzzle.as_mut() {
+                        state.solved = Some(false);
+                    }
+                    return false;
+                }
+            },
+        };
+        self.switched = false;
+        if buffered.hold {
+            // Undo goes back to before the hold, as it does for a hold after the piece came in.
+            let spawn_state = self.undo_state();
+            if self.swap_hold() && self.mode.is_practice() {
+                self.spawn_state = Some(spawn_state);
+            }
+        }
+        if !self.place_new_piece() {
+            return false;
+        }
+        if let Some(direction) = buffered.rotation {
+            self.finesse.rotated();
+            self.rotate_piece(direction);
+        }
+        true
+    }
+
+    fn undo_state(&self) -> UndoState {
+        UndoState {
+            board: self.board.clone(),
+            piece_bag: self.piece_bag.clone(),
+            piece: self.piece.clone(),
+            hold: self.hold.clone(),
+            score: self.score,
+            lines: self.lines,
+            level: self.level,
+            to_clear: self.to_clear,
+            speed: self.speed.load(Ordering::SeqCst),
+            cleared_last_round: self.cleared_last_round,
+            combo_counter: self.combo_counter,
+            history: self.history.len(),
+            opener: self.opener.clone(),
+            puzzle: self.puzzle.clone(),
+            stats: self.stats.clone(),
+            finesse: self.finesse.clone(),
+            hints_used: self.hints_used,
+        }
+    }
+
+    fn restore(&mut self, state: UndoState) {
+        self.effect = None;
+        self.board = state.board;
+        self.piece_bag = state.piece_bag;
+        self.piece = state.piece;
+        self.hold = state.hold;
+        self.switched = false;
+        self.score = state.score;
+        self.lines = state.lines;
+        self.level = state.level;
+        self.to_clear = state.to_clear;
+        self.speed.store(state.speed, Ordering::SeqCst);
+        self.cleared_last_round = state.cleared_last_round;
+        self.combo_counter = state.combo_counter;
+        self.history.truncate(state.history);
+        self.opener = state.opener;
+        self.puzzle = state.puzzle;
+        // The clock keeps running: time spent on a placement that was taken back still counts.
+        let duration = self.stats.duration;
+        self.stats = state.stats;
+        self.stats.duration = duration;
+        self.finesse = state.finesse;
+        self.hints_used = state.hints_used;
+        self.place_new_piece();
+    }
+
+    /// Goes back to when the previously locked piece spawned. Only available in practice modes.
+    fn undo(&mut self) -> bool {
+        match (self.undo.pop_back(), self.spawn_state.take()) {
+            (Some(previous), Some(current)) => {
+                self.redo.push(current);
+                self.restore(previous);
+                self.undos_used += 1;
+                true
+            }
+            (previous, current) => {
+                self.undo.extend(previous);
+                self.spawn_state = current;
+                false
+            }
+        }
+    }
+
+    /// Replays a placement taken back with undo.
+    fn redo(&mut self) -> bool {
+        match (self.redo.pop(), self.spawn_state.take()) {
+            (Some(next), Some(current)) => {
+                self.undo.push_back(current);
+                self.restore(next);
+                true
+            }
+            (next, current) => {
+                self.redo.extend(next);
+                self.spawn_state = current;
+                false
+            }
+        }
+    }
+
+    /// Starts the opener or puzzle being trained over from the beginning. Does nothing in other modes.
+    fn restart(&mut self) -> bool {
+        self.piece_bag = if let Some(trainer) = self.opener.as_mut() {
+            trainer.restart();
+            self.board = Board::new();
+            self.hold = None;
+            PieceBag::with_queue(self.seed, trainer.opener.queue)
+        } else if let Some(state) = self.puzzle.as_mut() {
+            state.restart();
+            self.board = state.puzzle.board.clone();
+            self.hold = state.puzzle.hold.map(Piece::new);
+            PieceBag::finite(&state.puzzle.queue)
+        } else {
+            return false;
+        };
+
+        self.effect = None;
+        self.piece = self.piece_bag.pop();
+        self.switched = false;
+        self.cleared_last_round = 0;
+        self.combo_counter = 0;
+        self.place_new_piece();
+        true
+    }
+
+    /// A T piece locking right after a rotation is a T-spin if at least three of the four cells diagonally
+    /// adjacent to its centre are blocked.
+    fn is_tspin(&self) -> bool {
+        if self.piece.kind != PieceKind::T || !self.last_rotated {
+            return false;
+        }
+        let corners = [(0, 0), (2, 0), (0, 2), (2, 2)];
+        corners
+            .iter()
+            .filter(|(dx, dy)| {
+                let x = self.piece_position.x + dx;
+                let y = self.piece_position.y + dy;
+                x < 0
+                    || x >= BOARD_WIDTH as i32
+                    || y >= BOARD_HEIGHT as i32
+                    || (y >= 0 && self.board.cells[y as usize][x as usize].is_some())
+            })
+            .count()
+            >= 3
+    }
+
+    /// Turns the hint overlay on or off. Turning it on shows a hint for the current piece right away.
+    fn toggle_hints(&mut self) -> bool {
+        self.show_hints = !self.show_hints;
+        self.update_hint();
+        true
+    }
+
+    /// Asks the bot for the best placement of the current piece if hints are turned on. Every hint shown
+    /// counts towards `hints_used`.
+    fn update_hint(&mut self) {
+        self.hint = None;
+        if self.show_hints {
+            let bot = self.bot.get_or_insert_with(Bot::load);
+            self.hint = bot.best_placement(&self.board, &self.piece, self.piece_position);
+            self.hints_used += 1;
+        }
+    }
+
+    /// Advances the game by moving the current piece down one step. If the piece cannot move down, the piece
+    /// is locked and the game is set up to drop the next piece.  Returns true if the game could be advanced,
+    /// false if the player has lost.
+    pub fn advance_game(&mut self) -> bool {
+        if self.spawn_at.is_some() {
+            return true;
+        }
+        if !self.move_piece(0, 1) {
+            if let Some(state) = self.spawn_state.take() {
+                self.undo.push_back(state);
+                if self.undo.len() > UNDO_LIMIT {
+                    self.undo.pop_front();
+                }
+                self.redo.clear();
+            }
+            if let Some(trainer) = self.opener.as_mut() {
+                if !trainer.check(&self.piece, self.piece_position) {
+                    return self.restart();
+                }
+            }
+            self.finesse.locked(
+                &self.board,
+                &self.piece,
+                self.piece_position,
+                self.rules.rotate_180,
+            );
+            let tspin = self.is_tspin();
+            self.history.push(Page {
+                piece: Some((self.piece.clone(), self.piece_position)),
+                ..Page::new(self.board.clone())
+            });
+            self.board.lock_piece(&self.piece, self.piece_position);
+            let full_rows = self.board.full_rows();
+            if self.animations {
+                let cells = self.piece.cells(self.piece_position);
+                let duration = Duration::from_millis(self.rules.line_clear_delay);
+                self.effect = Some(if full_rows.is_empty() || duration.is_zero() {
+                    Effect::lock(cells)
+                } else {
+                    Effect::clear(cells, self.board.clone(), full_rows, duration)
+                });
+            }
+            let cleared = self.board.clear_lines();
+            if self.cleared_last_round > 0 && cleared > 0 {
+                self.combo_counter += 1;
+            } else {
+                self.combo_counter = 0;
+            }
+            self.stats.locked(
+                self.piece.kind,
+                cleared,
+                tspin,
+                self.combo_counter,
+                cleared > 0 && self.board.is_empty(),
+            );
+            if let Some(state) = self.puzzle.as_mut() {
+                state.locked(cleared, tspin, &self.board);
+                if state.solved.is_some() {
+                    return false;
+                }
+            }
+            self.score += 100 * self.combo_counter;
+            match cleared {
+                1 => self.score += 100 * self.level,
+                2 => self.score += 300 * self.level,
+                3 => self.score += 500 * self.level,
+                4 => self.score += 800 * self.level,
+                _ => (),
+            }
+            self.cleared_last_round = cleared;
+            self.lines += cleared;
+            self.to_clear -= cleared as i32;
+            if self.to_clear <= 0 {
+                self.level += 1;
+                self.to_clear = self.level as i32 * 10;
+                let new_speed = 500 - (self.level - 1) * 10;
+
+                self.speed.store(new_speed as u64, Ordering::SeqCst);
+            }
+            let mut delay = self.rules.entry_delay;
+            if cleared > 0 {
+                delay += self.rules.line_clear_delay;
+            }
+            if delay == 0 {
+                return self.spawn_next_piece();
+            }
+            self.spawn_at = Some(Instant::now() + Duration::from_millis(delay));
+        }
+
+        true
+    }
+
+    /// Drops the current piece to the lowest spot on the board where it fits without collisions and
+    /// advances the game. Scores the rows dropped as a hard drop.
+    pub fn drop_piece(&mut self) -> bool {
+        let mut rows = 0;
+        while self.move_piece(0, 1) {
+            rows += 1;
+        }
+        self.score += rows * self.rules.hard_drop_points;
+        self.advance_game()
+    }
+
+    /// Performs `action`. Returns false if the game ended.
+    pub fn perform(&mut self, action: Action) -> bool {
+        if self.paused.load(Ordering::SeqCst) {
+            if action == Action::Pause {
+                self.pause();
+            }
+            return true;
+        }
+        if action.is_input() {
+            self.stats.keys += 1;
+        }
+        if self.spawn_at.is_some() {
+            match action {
+                Action::RotateLeft => self.buffered.rotation = Some(Direction::Left),
+                Action::RotateRight => self.buffered.rotation = Some(Direction::Right),
+                Action::Rotate180 if self.rules.rotate_180 => {
+                    self.buffered.rotation = Some(Direction::Half)
+                }
+                Action::Hold => self.buffered.hold = true,
+                _ if action.is_input() => {}
+                _ => return self.perform_now(action),
+            }
+            return true;
+        }
+        self.perform_now(action)
+    }
+
+    /// Performs `action` on the current piece.
+    fn perform_now(&mut self, action: Action) -> bool {
+        match action {
+            Action::MoveLeft | Action::MoveRight => {
+                let dx = if action == Action::MoveLeft { -1 } else { 1 };
+                self.finesse.moved(dx);
+                self.move_piece(dx, 0)
+            }
+            Action::SoftDrop => {
+                if self.move_piece(0, 1) {
+                    self.score += self.rules.soft_drop_points;
+                    return true;
+                }
+                return self.advance_game();
+            }
+            Action::HardDrop => return self.drop_piece(),
+            Action::SonicDrop => self.sonic_drop(),
+            Action::RotateLeft | Action::RotateRight => {
+                self.finesse.rotated();
+                let direction = if action == Action::RotateLeft {
+                    Direction::Left
+                } else {
+                    Direction::Right
+                };
+                self.rotate_piece(direction)
+            }
+            Action::Rotate180 if self.rules.rotate_180 => {
+                self.finesse.rotated();
+                self.rotate_piece(Direction::Half)
+            }
+            // Refused by the rule set
+            Action::Rotate180 => false,
+            Action::Hold => self.switch_hold(),
+            Action::Pause => self.pause(),
+            Action::Restart => self.restart(),
+            // The play loop handles quitting.
+            Action::Quit => false,
+            Action::Hints => self.toggle_hints(),
+            Action::Undo => self.undo(),
+            Action::Redo => self.redo(),
+            Action::ToggleHud(field) => {
+                let toggled = self.hud.toggle(field);
+                let hud = self.hud;
+                // Not being able to remember the setting shouldn't interrupt the game.
+                let _ = Settings::update(|s| s.hud = hud);
+                toggled
+            }
+        };
+        true
+    }
+
+    /// Shows the game's statistics on the display.
+    fn render_stats(&self, display: &mut impl Renderer) {
+        display.set_text("Game over", 2, 1, Color::Text, Color::Black);
+        display.set_text(
+            format!("Score: {}  Lines: {}", self.score, self.lines),
+            2,
+            3,
+            Color::Text,
+            Color::Black,
+        );
+        for (i, line) in self.stats.summary().iter().enumerate() {
+            display.set_text(line, 2, 5 + i as u32, Color::Text, Color::Black);
+        }
+        display.set_text(
+            "Press any key to continue",
+            2,
+            BOARD_HEIGHT,
+            Color::Highlight,
+            Color::Black,
+        );
+    }
+
+    pub fn play(&mut self, display: &mut impl Renderer) {
+        let (tx_event, rx_event) = mpsc::channel();
+        // Spawn a thread which sends periodic game ticks to advance the piece
+        {
+            let tx_event = tx_event.clone();
+            let arc = self.speed.clone();
+            let p = self.paused.clone();
+            thread::spawn(move || loop {
+                let dur = Duration::from_millis(arc.load(Ordering::SeqCst));
+                thread::sleep(dur);
+                if !p.load(Ordering::SeqCst) && tx_event.send(GameUpdate::Tick).is_err() {
+                    break;
+                }
+            });
+        }
+
+        // Spawn a thread which forwards terminal resizes
+        let mut signals = Signals::new([SIGWINCH]).unwrap();
+        let signals_handle = signals.handle();
+        {
+            let tx_event = tx_event.clone();
+            thread::spawn(move || {
+                for _ in signals.forever() {
+                    if tx_event.send(GameUpdate::Resize).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        // Spawn a thread which listens for keyboard input
+        let tx_event = tx_event.clone();
+        let (flag, control) = thread_control::make_pair();
+
+        let input_handle = thread::spawn(move || {
+            while flag.alive() {
+                if let Some(key) = input::read_key(Some(INPUT_POLL)) {
+                    if tx_event.send(GameUpdate::KeyPress(key)).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        // Main game loop. The loop listens and responds to timer and keyboard updates received on a channel
+        // as sent by the threads spawned above.
+        self.clock = Some(Instant::now());
+        loop {
+            if self.effect.as_ref().is_some_and(Effect::is_over) {
+                self.effect = None;
+            }
+            display.clear_buffer();
+            self.render(display);
+            display.render();
+            // Don't let pieces fall while the player can't see them
+            if !display.fits() && !self.paused.load(Ordering::SeqCst) {
+                self.pause();
+            }
+
+            // Wait for the next event, until the entry delay is over or for the next frame of an effect
+            let spawn_at = self
+                .spawn_at
+                .filter(|_| !self.paused.load(Ordering::SeqCst));
+            let mut wait = spawn_at.map(|at| at.saturating_duration_since(Instant::now()));
+            if self.effect.is_some() {
+                wait = Some(wait.map_or(effects::FRAME, |w| w.min(effects::FRAME)));
+            }
+            let update = match wait {
+                Some(wait) => match rx_event.recv_timeout(wait) {
+                    Err(mpsc::RecvTimeoutError::Timeout)
+                        if spawn_at.is_some_and(|at| at <= Instant::now()) =>
+                    {
+                        Ok(GameUpdate::Spawn)
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => Ok(GameUpdate::Frame),
+                    update => update.map_err(|_| mpsc::RecvError),
+                },
+                None => rx_event.recv(),
+            };
+            match update {
+                Ok(update) => {
+                    match update {
+                        GameUpdate::KeyPress(key) => match self.keymap.action(&key) {
+                            Some(Action::Quit) => {
+                                // Bring in the next piece, so that a saved game continues with it
+                                if self.spawn_at.is_some() {
+                                    self.spawn_next_piece();
+                                }
+                                self.quit = true;
+                                break;
+                            }
+                            Some(action) if !self.perform(action) => break,
+                            _ => {}
+                        },
+                        GameUpdate::Tick => {
+                            if !self.advance_game() {
+                                break;
+                            }
+                        }
+                        GameUpdate::Spawn => {
+                            if !self.spawn_next_piece() {
+                                break;
+                            }
+                        }
+                        GameUpdate::Resize | GameUpdate::Frame => {}
+                    };
+                }
+                Err(err) => panic!("{}", err),
+            }
+        }
+        self.stop_clock();
+
+        if !self.quit {
+            display.clear_buffer();
+            self.render_stats(display);
+            display.render();
+            while let Ok(update) = rx_event.recv() {
+                match update {
+                    GameUpdate::KeyPress(_) => break,
+                    GameUpdate::Resize => display.render(),
+                    GameUpdate::Tick | GameUpdate::Spawn | GameUpdate::Frame => {}
+                }
+            }
+        }
+        signals_handle.close();
+
+        control.stop();
+        input_handle.join().unwrap(); //to prevent input thread from eating input
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::puzzle;
+
+    fn perform(game: &mut Game, actions: &[Action]) -> bool {
+        actions.iter().all(|&action| game.perform(action))
+    }
+
+    #[test]
+    fn held_piece_comes_in_after_the_queue() {
+        let mut game = Game::with_puzzle(puzzle::load("tetris").unwrap());
+        // Hold the I, put the O in the corner and then drop the I down the well
+        assert!(perform(&mut game, &[Action::Hold]));
+        assert_eq!(game.piece.kind, PieceKind::O);
+        assert!(perform(&mut game, &[Action::MoveLeft; 4]));
+        assert!(perform(&mut game, &[Action::HardDrop]));
+        assert_eq!(game.piece.kind, PieceKind::I);
+        assert!(game.hold.is_none());
+        assert!(perform(&mut game, &[Action::RotateRight]));
+        assert!(perform(&mut game, &[Action::MoveRight; 4]));
+        assert!(!perform(&mut game, &[Action::HardDrop]));
+        assert_eq!(game.puzzle.unwrap().solved, Some(true));
+    }
+
+    #[test]
+    fn puzzle_fails_once_queue_and_hold_run_out() {
+        let text = puzzle::BUILTIN[2].1.replace("queue", "hold = \"O\"\nqueue");
+        let mut game = Game::with_puzzle(puzzle::parse(&text).unwrap());
+        for kind in [PieceKind::I, PieceKind::Z, PieceKind::S, PieceKind::O] {
+            assert_eq!(game.piece.kind, kind);
+            let going = perform(&mut game, &[Action::HardDrop]);
+            assert_eq!(going, kind != PieceKind::O);
+        }
+        assert_eq!(game.puzzle.unwrap().solved, Some(false));
+    }
+
+    #[test]
+    fn finesse_counts_from_the_piece_swapped_in() {
+        let mut game = Game::with_seed(Mode::Finesse, 1);
+        game.rules.rotate_180 = true;
+        assert!(perform(&mut game, &[Action::Hold]));
+        assert_ne!(game.piece.kind, PieceKind::O);
+        assert!(perform(&mut game, &[Action::Rotate180, Action::HardDrop]));
+        assert!(perform(&mut game, &[Action::Hold]));
+        assert!(perform(&mut game, &[Action::RotateRight, Action::HardDrop]));
+        assert_eq!(game.finesse.total_pieces(), 2);
+        assert_eq!(game.finesse.total_faults(), 0);
+    }
+
+    #[test]
+    fn undo_restores_the_piece_before() {
+        let mut game = Game::with_seed(Mode::Finesse, 3);
+        game.rules.hard_drop_points = 2;
+        let piece = game.piece.kind;
+        let queue = game.piece_bag.remaining();
+        assert!(perform(&mut game, &[Action::Hold, Action::HardDrop]));
+        assert!(game.score > 0);
+        assert!(game.board.cells.iter().flatten().any(|c| c.is_some()));
+        assert_ne!(game.piece_bag.remaining(), queue);
+
+        assert!(perform(&mut game, &[Action::Undo]));
+        assert_eq!(game.piece.kind, piece);
+        assert!(game.hold.is_none());
+        assert_eq!(game.piece_bag.remaining(), queue);
+        assert_eq!(game.score, 0);
+        assert_eq!(game.stats.pieces, 0);
+        assert!(game.board.cells.iter().flatten().all(|c| c.is_none()));
+        // There is nothing before the first piece
+        assert!(perform(&mut game, &[Action::Undo]));
+        assert_eq!(game.undos_used, 1);
+        assert_eq!(game.piece.kind, piece);
+    }
+
+    #[test]
+    fn undo_makes_the_game_unscored() {
+        let mut game = Game::with_seed(Mode::Finesse, 3);
+        assert!(crate::is_scored(&game));
+        assert!(perform(&mut game, &[Action::HardDrop, Action::Undo]));
+        assert_eq!(game.undos_used, 1);
+        assert!(!crate::is_scored(&game));
+        // Redoing doesn't make up for it
+        assert!(perform(&mut game, &[Action::Redo]));
+        assert!(!crate::is_scored(&game));
+    }
+}
@@ -0,0 +1,70 @@
+use crate::stats::Stats;
+use serde::{Deserialize, Serialize};
+
+/// Which live statistics the sidebar shows. Toggled in game with the number keys and remembered between
+/// games with the other settings.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct Hud {
+    pub timer: bool,
+    pub pps: bool,
+    pub apm: bool,
+    pub kpp: bool,
+    pub lines: bool,
+}
+
+impl Default for Hud {
+    fn default() -> Hud {
+        Hud {
+            timer: true,
+            pps: true,
+            apm: true,
+            kpp: true,
+            lines: true,
+        }
+    }
+}
+
+impl Hud {
+    /// Turns field `index` (1 to 5, in the order the fields are shown) on or off. Returns false if there is
+    /// no such field.
+    pub fn toggle(&mut self, index: u32) -> bool {
+        let field = match index {
+            1 => &mut self.timer,
+            2 => &mut self.pps,
+            3 => &mut self.apm,
+            4 => &mut self.kpp,
+            5 => &mut self.lines,
+            _ => return false,
+        };
+        *field = !*field;
+        true
+    }
+
+    /// Returns the enabled fields as text, after `seconds` of play.
+    pub fn fields(&self, stats: &Stats, seconds: f64, lines: u32) -> Vec<String> {
+        let mut fields = Vec::new();
+        if self.timer {
+            let tenths = (seconds * 10.0) as u64;
+            fields.push(format!(
+                "Time: {}:{:02}.{}",
+                tenths / 600,
+                tenths / 10 % 60,
+                tenths % 10
+            ));
+        }
+        if self.pps {
+            fields.push(format!("PPS: {:.2}", stats.pieces_per_second(seconds)));
+        }
+        if self.apm {
+            fields.push(format!("APM: {:.1}", stats.attack_per_minute(seconds)));
+        }
+        if self.kpp {
+            fields.push(format!("KPP: {:.2}", stats.keys_per_piece()));
+        }
+        if self.lines {
+            fields.push(format!("Lines: {}", lines));
+        }
+        fields
+    }
+}
@@ -0,0 +1,597 @@
+use crate::keymap::{Key, Modifiers};
+use std::io::Read;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for the rest of an escape sequence before taking ESC as the Esc key.
+const ESC_TIMEOUT: Duration = Duration::from_millis(50);
+
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Something typed into the terminal.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Key(Key),
+    /// Text pasted into the terminal while bracketed paste is on.
+    Paste(String),
+}
+
+enum Token {
+    Key(Key),
+    /// The start of a bracketed paste.
+    PasteStart,
+    /// Bytes that aren't a key, such as a mouse report or an unknown sequence.
+    Ignored,
+}
+
+enum Parsed {
+    /// A token and the number of bytes it took.
+    Token(Token, usize),
+    /// The bytes are the start of a token, but there aren't enough of them yet.
+    Incomplete,
+}
+
+/// Turns the bytes a terminal sends into events. Bytes can be fed in any pieces; an event is returned
+/// once all of its bytes are in.
+#[derive(Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    /// The text pasted so far, while a bracketed paste is going on.
+    paste: Option<Vec<u8>>,
+}
+
+impl Decoder {
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the next event in the bytes fed so far, or None if there isn't a whole one yet.
+    pub fn event(&mut self) -> Option<Event> {
+        loop {
+            if self.paste.is_some() {
+                return self.paste_event();
+            }
+            if self.buffer.is_empty() {
+                return None;
+            }
+            match parse(&self.buffer) {
+                Parsed::Incomplete => return None,
+                Parsed::Token(token, len) => {
+                    self.buffer.drain(..len);
+                    match token {
+                        Token::Key(key) => return Some(Event::Key(key)),
+                        Token::PasteStart => self.paste = Some(Vec::new()),
+                        Token::Ignored => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether the bytes fed so far end in an unfinished key.
+    pub fn pending(&self) -> bool {
+        self.paste.is_none() && !self.buffer.is_empty()
+    }
+
+    /// Called when no more bytes came for a while. A lone ESC is then the Esc key rather than the start of
+    /// an escape sequence, and an unfinished sequence is taken as Esc followed by ordinary keys.
+    pub fn timeout(&mut self) -> Option<Event> {
+        if let Some(event) = self.event() {
+            return Some(event);
+        }
+        if !self.pending() {
+            return None;
+        }
+        // What's left can only be an unfinished escape sequence or UTF-8 character
+        if self.buffer.remove(0) == 0x1b {
+            Some(Event::Key(Key::Esc))
+        } else {
+            self.timeout()
+        }
+    }
+
+    fn paste_event(&mut self) -> Option<Event> {
+        let paste = self.paste.as_mut()?;
+        match self
+            .buffer
+            .windows(PASTE_END.len())
+            .position(|w| w == PASTE_END)
+        {
+            Some(end) => {
+                paste.extend(self.buffer.drain(..end));
+                self.buffer.drain(..PASTE_END.len());
+                let text = String::from_utf8_lossy(&self.paste.take()?).into_owned();
+                Some(Event::Paste(text))
+            }
+            None => {
+                // Keep back what could be the start of the end marker
+                let buffer = &self.buffer;
+                let keep = (1..PASTE_END.len())
+                    .rev()
+                    .find(|&n| buffer.ends_with(&PASTE_END[..n]))
+                    .unwrap_or(0);
+                let len = self.buffer.len() - keep;
+                paste.extend(self.buffer.drain(..len));
+                None
+            }
+        }
+    }
+}
+
+fn key(key: Key, len: usize) -> Parsed {
+    Parsed::Token(Token::Key(key), len)
+}
+
+fn ignored(len: usize) -> Parsed {
+    Parsed::Token(Token::Ignored, len)
+}
+
+fn parse(bytes: &[u8]) -> Parsed {
+    match bytes[0] {
+        0x1b => parse_escape(bytes),
+        b'\r' | b'\n' => key(Key::Enter, 1),
+        b'\t' => key(Key::Tab, 1),
+        0x7f | 0x08 => key(Key::Backspace, 1),
+        c @ 1..=26 => key(Key::Ctrl((b'a' + c - 1) as char), 1),
+        c if c.is_ascii_control() => ignored(1),
+        c if c.is_ascii() => key(Key::Char(c as char), 1),
+        _ => parse_utf8(bytes),
+    }
+}
+
+fn parse_utf8(bytes: &[u8]) -> Parsed {
+    let bytes = &bytes[..bytes.len().min(4)];
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) if e.valid_up_to() > 0 => std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+        Err(e) => {
+            return match e.error_len() {
+                Some(len) => ignored(len),
+                None => Parsed::Incomplete,
+            }
+        }
+    };
+    let c = text.chars().next().unwrap();
+    key(Key::Char(c), c.len_utf8())
+}
+
+/// Parses a sequence starting with ESC: a CSI or SS3 sequence, or a key typed with Alt held down.
+fn parse_escape(bytes: &[u8]) -> Parsed {
+    match bytes.get(1) {
+        None => Parsed::Incomplete,
+        Some(b'[') => parse_csi(bytes),
+        Some(b'O') => parse_ss3(bytes),
+        Some(0x1b) => key(Key::Esc, 1),
+        Some(_) => match parse(&bytes[1..]) {
+            Parsed::Token(Token::Key(k), len) => key(k.with(Modifiers::ALT), len + 1),
+            Parsed::Token(_, _) => key(Key::Esc, 1),
+            Parsed::Incomplete => Parsed::Incomplete,
+        },
+    }
+}
+
+/// Parses `ESC [`, parameters separated by `;` and a final byte. The second parameter holds the modifiers.
+fn parse_csi(bytes: &[u8]) -> Parsed {
+    let body = &bytes[2..];
+    // The Linux console sends F1 to F5 as ESC [ [ A to ESC [ [ E
+    if body.first() == Some(&b'[') {
+        return match body.get(1) {
+            None => Parsed::Incomplete,
+            Some(&c @ b'A'..=b'E') => key(Key::F(c - b'A' + 1), 4),
+            Some(_) => ignored(3),
+        };
+    }
+
+    let end = match body.iter().position(|c| !(0x20..=0x3f).contains(c)) {
+        Some(end) => end,
+        None => return Parsed::Incomplete,
+    };
+    let len = 2 + end + 1;
+    let final_byte = body[end];
+    if !(0x40..=0x7e).contains(&final_byte) {
+        // A broken sequence: skip it, and read the byte that broke it as a key of its own
+        return ignored(len - 1);
+    }
+    // Private sequences such as mouse reports start with one of < = > ?
+    if body[..end]
+        .first()
+        .is_some_and(|c| (b'<'..=b'?').contains(c))
+    {
+        return ignored(len);
+    }
+
+    let params: Vec<u32> = String::from_utf8_lossy(&body[..end])
+        .split(';')
+        .map(|p| p.split(':').next().unwrap_or("").parse().unwrap_or(0))
+        .collect();
+    let param = |i: usize| params.get(i).copied().unwrap_or(0);
+    let k = match (final_byte, param(0)) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) => Key::Home,
+        (b'F', _) => Key::End,
+        (c @ b'P'..=b'S', _) => Key::F(c - b'P' + 1),
+        (b'Z', _) => Key::Tab.with(Modifiers::SHIFT),
+        (b'~', n) => match n {
+            1 | 7 => Key::Home,
+            2 => Key::Insert,
+            3 => Key::Delete,
+            4 | 8 => Key::End,
+            5 => Key::PageUp,
+            6 => Key::PageDown,
+            11..=15 => Key::F(n as u8 - 10),
+            17..=21 => Key::F(n as u8 - 11),
+            23 | 24 => Key::F(n as u8 - 12),
+            200 => return Parsed::Token(Token::PasteStart, len),
+            _ => return ignored(len),
+        },
+        // Keys as sent by terminals that report every key by its code point
+        (b'u', n) => match n {
+            9 => Key::Tab,
+            13 => Key::Enter,
+            27 => Key::Esc,
+            127 => Key::Backspace,
+            _ => match char::from_u32(n).filter(|c| !c.is_control()) {
+                Some(c) => Key::Char(c),
+                None => return ignored(len),
+            },
+        },
+        _ => return ignored(len),
+    };
+    key(k.with(Modifiers::from_param(param(1))), len)
+}
+
+/// Parses `ESC O`, an optional modifier parameter and a final byte, as sent for arrows and F1 to F4 by
+/// terminals in application mode.
+fn parse_ss3(bytes: &[u8]) -> Parsed {
+    let body = &bytes[2..];
+    let end = match body.iter().position(|c| !c.is_ascii_digit()) {
+        Some(end) => end,
+        None => return Parsed::Incomplete,
+    };
+    let len = 2 + end + 1;
+    let k = match body[end] {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'H' => Key::Home,
+        b'F' => Key::End,
+        b'M' => Key::Enter,
+        c @ b'P'..=b'S' => Key::F(c - b'P' + 1),
+        // Alt-O followed by something else
+        c if end == 0 && !(0x40..=0x7e).contains(&c) => {
+            return key(Key::Char('O').with(Modifiers::ALT), 2)
+        }
+        _ => return ignored(len),
+    };
+    let modifiers = String::from_utf8_lossy(&body[..end]).parse().unwrap_or(0);
+    key(k.with(Modifiers::from_param(modifiers)), len)
+}
+
+/// Standard input, read on a thread of its own so that waiting for it can time out.
+struct Input {
+    bytes: Receiver<Vec<u8>>,
+    decoder: Decoder,
+}
+
+fn input() -> &'static Mutex<Input> {
+    static INPUT: OnceLock<Mutex<Input>> = OnceLock::new();
+    INPUT.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let stdin = &mut std::io::stdin();
+            let mut buffer = [0; 256];
+            loop {
+                let len = match stdin.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(len) => len,
+                    Err(msg) => panic!("could not read from standard in: {}", msg),
+                };
+                if tx.send(buffer[..len].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        Mutex::new(Input {
+            bytes: rx,
+            decoder: Decoder::default(),
+        })
+    })
+}
+
+/// Waits for the next event on standard input, for at most `timeout` if there is one. Returns None if
+/// nothing was typed in time or standard input is closed.
+pub fn read(timeout: Option<Duration>) -> Option<Event> {
+    let input = &mut *input().lock().unwrap();
+    let deadline = timeout.map(|t| Instant::now() + t);
+    loop {
+        if let Some(event) = input.decoder.event() {
+            return Some(event);
+        }
+        // The rest of an escape sequence arrives right away, if at all
+        let wait = if input.decoder.pending() {
+            Some(ESC_TIMEOUT)
+        } else {
+            deadline.map(|d| d.saturating_duration_since(Instant::now()))
+        };
+        let received = match wait {
+            Some(wait) => input.bytes.recv_timeout(wait),
+            None => input
+                .bytes
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(bytes) => input.decoder.feed(&bytes),
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(event) = input.decoder.timeout() {
+                    return Some(event);
+                }
+                if deadline.is_some_and(|d| Instant::now() >= d) {
+                    return None;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return input.decoder.timeout(),
+        }
+    }
+}
+
+/// Like `read`, but returns None for pasted text rather than pressing a key for every character in it.
+pub fn read_key(timeout: Option<Duration>) -> Option<Key> {
+    match read(timeout)? {
+        Event::Key(key) => Some(key),
+        Event::Paste(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(chunks: &[&[u8]]) -> Vec<Event> {
+        let mut decoder = Decoder::default();
+        let mut events = Vec::new();
+        for chunk in chunks {
+            decoder.feed(chunk);
+            while let Some(event) = decoder.event() {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    fn keys(bytes: &[u8]) -> Vec<Key> {
+        decode(&[bytes])
+            .into_iter()
+            .map(|e| match e {
+                Event::Key(key) => key,
+                Event::Paste(text) => panic!("unexpected paste {:?}", text),
+            })
+            .collect()
+    }
+
+    fn modified(modifiers: Modifiers, key: Key) -> Key {
+        Key::Modified(modifiers, Box::new(key))
+    }
+
+    #[test]
+    fn plain_keys() {
+        assert_eq!(
+            keys(b"a Z\r\t\x7f\x12"),
+            vec![
+                Key::Char('a'),
+                Key::Char(' '),
+                Key::Char('Z'),
+                Key::Enter,
+                Key::Tab,
+                Key::Backspace,
+                Key::Ctrl('r'),
+            ]
+        );
+    }
+
+    #[test]
+    fn utf8() {
+        assert_eq!(
+            keys("é€😀".as_bytes()),
+            vec![Key::Char('é'), Key::Char('€'), Key::Char('😀')]
+        );
+        // Invalid bytes are skipped
+        assert_eq!(keys(b"\xff\x80x"), vec![Key::Char('x')]);
+    }
+
+    #[test]
+    fn utf8_split_across_reads() {
+        let bytes = "😀".as_bytes();
+        assert_eq!(
+            decode(&[&bytes[..1], &bytes[1..3], &bytes[3..]]),
+            vec![Event::Key(Key::Char('😀'))]
+        );
+    }
+
+    #[test]
+    fn csi_and_ss3_keys() {
+        assert_eq!(
+            keys(b"\x1b[A\x1b[B\x1b[C\x1b[D\x1b[H\x1b[F\x1bOA\x1bOH\x1bOF"),
+            vec![
+                Key::Up,
+                Key::Down,
+                Key::Right,
+                Key::Left,
+                Key::Home,
+                Key::End,
+                Key::Up,
+                Key::Home,
+                Key::End,
+            ]
+        );
+        assert_eq!(
+            keys(b"\x1b[1~\x1b[2~\x1b[3~\x1b[4~\x1b[5~\x1b[6~\x1b[7~\x1b[8~"),
+            vec![
+                Key::Home,
+                Key::Insert,
+                Key::Delete,
+                Key::End,
+                Key::PageUp,
+                Key::PageDown,
+                Key::Home,
+                Key::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn function_keys() {
+        let bytes = b"\x1bOP\x1bOQ\x1bOR\x1bOS\x1b[15~\x1b[17~\x1b[18~\x1b[19~\x1b[20~\x1b[21~\x1b[23~\x1b[24~";
+        assert_eq!(keys(bytes), (1..=12).map(Key::F).collect::<Vec<_>>());
+        assert_eq!(
+            keys(b"\x1b[11~\x1b[[A\x1b[[E"),
+            vec![Key::F(1), Key::F(1), Key::F(5)]
+        );
+    }
+
+    #[test]
+    fn modifiers() {
+        let ctrl_shift = Modifiers {
+            shift: true,
+            ctrl: true,
+            ..Modifiers::default()
+        };
+        assert_eq!(
+            keys(b"\x1b[1;5C\x1b[1;2A\x1b[3;3~\x1b[1;6P\x1bO5D\x1b[15;2~\x1b[Z"),
+            vec![
+                modified(Modifiers::CTRL, Key::Right),
+                modified(Modifiers::SHIFT, Key::Up),
+                modified(Modifiers::ALT, Key::Delete),
+                modified(ctrl_shift, Key::F(1)),
+                modified(Modifiers::CTRL, Key::Left),
+                modified(Modifiers::SHIFT, Key::F(5)),
+                modified(Modifiers::SHIFT, Key::Tab),
+            ]
+        );
+    }
+
+    #[test]
+    fn alt_keys() {
+        assert_eq!(
+            keys(b"\x1bx\x1b\x12\x1b\x7f\x1bO\x1b"),
+            vec![
+                modified(Modifiers::ALT, Key::Char('x')),
+                modified(Modifiers::ALT, Key::Ctrl('r')),
+                modified(Modifiers::ALT, Key::Backspace),
+                modified(Modifiers::ALT, Key::Char('O')),
+            ]
+        );
+    }
+
+    #[test]
+    fn code_point_keys() {
+        assert_eq!(
+            keys(b"\x1b[114;5u\x1b[97;2u\x1b[27u\x1b[13;3u"),
+            vec![
+                Key::Ctrl('r'),
+                Key::Char('A'),
+                Key::Esc,
+                modified(Modifiers::ALT, Key::Enter),
+            ]
+        );
+    }
+
+    #[test]
+    fn sequences_split_across_reads() {
+        assert_eq!(
+            decode(&[b"\x1b", b"[", b"1;", b"5", b"A", b"\x1bO", b"P"]),
+            vec![
+                Event::Key(modified(Modifiers::CTRL, Key::Up)),
+                Event::Key(Key::F(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn lone_esc_after_timeout() {
+        let mut decoder = Decoder::default();
+        decoder.feed(b"\x1b");
+        assert_eq!(decoder.event(), None);
+        assert!(decoder.pending());
+        assert_eq!(decoder.timeout(), Some(Event::Key(Key::Esc)));
+        assert!(!decoder.pending());
+
+        // An unfinished sequence is Esc followed by the keys typed
+        decoder.feed(b"\x1b[1;");
+        assert_eq!(decoder.event(), None);
+        assert_eq!(decoder.timeout(), Some(Event::Key(Key::Esc)));
+        let rest: Vec<_> = std::iter::from_fn(|| decoder.event()).collect();
+        assert_eq!(
+            rest,
+            vec![
+                Event::Key(Key::Char('[')),
+                Event::Key(Key::Char('1')),
+                Event::Key(Key::Char(';')),
+            ]
+        );
+        assert_eq!(keys(b"\x1b\x1b[A"), vec![Key::Esc, Key::Up]);
+    }
+
+    #[test]
+    fn ignored_sequences() {
+        // A mouse report, a cursor position report and an unknown key
+        assert_eq!(
+            keys(b"\x1b[<0;3;4M\x1b[?1;2c\x1b[99~a"),
+            vec![Key::Char('a')]
+        );
+        // A sequence broken off by a control character
+        assert_eq!(keys(b"\x1b[1\x01"), vec![Key::Ctrl('a')]);
+    }
+
+    #[test]
+    fn bracketed_paste() {
+        assert_eq!(
+            decode(&[b"a\x1b[200~hello\x1b[A wor", b"ld\x1b[20", b"1~b"]),
+            vec![
+                Event::Key(Key::Char('a')),
+                Event::Paste("hello\x1b[A world".to_string()),
+                Event::Key(Key::Char('b')),
+            ]
+        );
+        let mut decoder = Decoder::default();
+        decoder.feed(b"\x1b[200~slow");
+        assert_eq!(decoder.event(), None);
+        assert!(!decoder.pending());
+        assert_eq!(decoder.timeout(), None);
+    }
+
+    #[test]
+    fn key_names_round_trip() {
+        let mut inputs: Vec<Vec<u8>> = Vec::new();
+        for byte in 0..0x80 {
+            inputs.push(vec![byte]);
+            inputs.push(vec![0x1b, byte]);
+        }
+        for modifiers in 1..=16 {
+            for c in "ABCDHFPQRSZ".chars() {
+                inputs.push(format!("\x1b[1;{}{}", modifiers, c).into_bytes());
+            }
+            for c in "ABCDHFMPQRS".chars() {
+                inputs.push(format!("\x1bO{}{}", modifiers, c).into_bytes());
+            }
+            for n in 1..=24 {
+                inputs.push(format!("\x1b[{};{}~", n, modifiers).into_bytes());
+            }
+            for n in (32..127).chain([9, 13, 27, 127, 0xe9]) {
+                inputs.push(format!("\x1b[{};{}u", n, modifiers).into_bytes());
+            }
+        }
+        for input in inputs {
+            for key in keys(&input) {
+                let name = key.to_string();
+                assert_eq!(Key::from_name(&name), Some(key), "{:?} is {}", input, name);
+            }
+        }
+    }
+}
@@ -0,0 +1,617 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+
+/// A key as read from the terminal.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// A function key, F1 to F12.
+    F(u8),
+    Enter,
+    Tab,
+    Backspace,
+    Esc,
+    /// A letter typed with Ctrl held down.
+    Ctrl(char),
+    Char(char),
+    /// Any other key pressed with modifiers held down.
+    Modified(Modifiers, Box<Key>),
+}
+
+/// The modifier keys held down with a key.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl Modifiers {
+    pub const SHIFT: Modifiers = Modifiers {
+        shift: true,
+        alt: false,
+        ctrl: false,
+    };
+    pub const ALT: Modifiers = Modifiers {
+        shift: false,
+        alt: true,
+        ctrl: false,
+    };
+    pub const CTRL: Modifiers = Modifiers {
+        shift: false,
+        alt: false,
+        ctrl: true,
+    };
+
+    /// The modifiers encoded in the parameter of an xterm escape sequence, which is one more than a bit
+    /// mask of Shift (1), Alt (2) and Ctrl (4). Meta (8) is taken as Alt.
+    pub fn from_param(param: u32) -> Modifiers {
+        let bits = param.saturating_sub(1);
+        Modifiers {
+            shift: bits & 1 != 0,
+            alt: bits & (2 | 8) != 0,
+            ctrl: bits & 4 != 0,
+        }
+    }
+
+    pub fn is_empty(self) -> bool {
+        self == Modifiers::default()
+    }
+
+    fn union(self, other: Modifiers) -> Modifiers {
+        Modifiers {
+            shift: self.shift || other.shift,
+            alt: self.alt || other.alt,
+            ctrl: self.ctrl || other.ctrl,
+        }
+    }
+}
+
+impl Key {
+    /// Parses a key as written in the keymap file: a single character, `space`, `up`, `down`, `left`,
+    /// `right`, `home`, `end`, `insert`, `delete`, `pageup`, `pagedown`, `enter`, `tab`, `backspace`,
+    /// `esc` or `f1` to `f12`, after any of the prefixes `ctrl-`, `alt-` and `shift-`.
+    pub fn from_name(name: &str) -> Option<Key> {
+        let mut modifiers = Modifiers::default();
+        let mut rest = name;
+        while rest.chars().count() > 1 {
+            let prefix = rest.split('-').next().unwrap_or("").to_lowercase();
+            match prefix.as_str() {
+                "ctrl" => modifiers.ctrl = true,
+                "alt" => modifiers.alt = true,
+                "shift" => modifiers.shift = true,
+                _ => break,
+            }
+            // A modifier on its own names no key
+            rest = rest[prefix.len()..].strip_prefix('-')?;
+        }
+
+        let mut chars = rest.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Some(Key::Char(c).with(modifiers));
+        }
+        let lower = rest.to_lowercase();
+        let key = match lower.as_str() {
+            "space" => Key::Char(' '),
+            "up" => Key::Up,
+            "down" => Key::Down,
+            "left" => Key::Left,
+            "right" => Key::Right,
+            "home" => Key::Home,
+            "end" => Key::End,
+            "insert" => Key::Insert,
+            "delete" => Key::Delete,
+            "pageup" => Key::PageUp,
+            "pagedown" => Key::PageDown,
+            "enter" => Key::Enter,
+            "tab" => Key::Tab,
+            "backspace" => Key::Backspace,
+            "esc" => Key::Esc,
+            _ => {
+                let n = lower.strip_prefix('f')?.parse().ok()?;
+                if !(1..=12).contains(&n) {
+                    return None;
+                }
+                Key::F(n)
+            }
+        };
+        Some(key.with(modifiers))
+    }
+
+    /// This key pressed with `modifiers` as well, written the way the terminal sends it: Shift turns a
+    /// character into its upper case and Ctrl turns a letter into `Ctrl`, with any other modifiers on top.
+    /// Every combination has only this one form, so a key read back from its name is the key decoded.
+    pub fn with(self, modifiers: Modifiers) -> Key {
+        let without = |modifier: Modifiers| Modifiers {
+            shift: modifiers.shift && !modifier.shift,
+            alt: modifiers.alt && !modifier.alt,
+            ctrl: modifiers.ctrl && !modifier.ctrl,
+        };
+        match self {
+            _ if modifiers.is_empty() => self,
+            Key::Char(c) if modifiers.shift => {
+                Key::Char(c.to_ascii_uppercase()).with(without(Modifiers::SHIFT))
+            }
+            Key::Char(c) if modifiers.ctrl && c.is_ascii_alphabetic() => {
+                Key::Ctrl(c.to_ascii_lowercase()).with(without(Modifiers::CTRL))
+            }
+            Key::Ctrl(c) if modifiers.ctrl => Key::Ctrl(c).with(without(Modifiers::CTRL)),
+            Key::Modified(inner, key) => (*key).with(inner.union(modifiers)),
+            key => Key::Modified(modifiers, Box::new(key)),
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Key::Up => write!(f, "up"),
+            Key::Down => write!(f, "down"),
+            Key::Left => write!(f, "left"),
+            Key::Right => write!(f, "right"),
+            Key::Home => write!(f, "home"),
+            Key::End => write!(f, "end"),
+            Key::Insert => write!(f, "insert"),
+            Key::Delete => write!(f, "delete"),
+            Key::PageUp => write!(f, "pageup"),
+            Key::PageDown => write!(f, "pagedown"),
+            Key::F(n) => write!(f, "f{}", n),
+            Key::Enter => write!(f, "enter"),
+            Key::Tab => write!(f, "tab"),
+            Key::Backspace => write!(f, "backspace"),
+            Key::Esc => write!(f, "esc"),
+            Key::Ctrl(c) => write!(f, "ctrl-{}", c),
+            Key::Char(' ') => write!(f, "space"),
+            Key::Char(c) => write!(f, "{}", c),
+            Key::Modified(modifiers, key) => {
+                if modifiers.ctrl {
+                    write!(f, "ctrl-")?;
+                }
+                if modifiers.alt {
+                    write!(f, "alt-")?;
+                }
+                if modifiers.shift {
+                    write!(f, "shift-")?;
+                }
+                write!(f, "{}", key)
+            }
+        }
+    }
+}
+
+/// Something the player can do in a game.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    /// Moves the piece down one row, locking it if it can't move.
+    SoftDrop,
+    /// Drops the piece to the bottom and locks it.
+    HardDrop,
+    /// Drops the piece to the bottom without locking it.
+    SonicDrop,
+    RotateLeft,
+    RotateRight,
+    Rotate180,
+    Hold,
+    Pause,
+    /// Starts an opener or a puzzle again.
+    Restart,
+    Quit,
+    Hints,
+    Undo,
+    Redo,
+    /// Shows or hides HUD field 1 to 5.
+    ToggleHud(u32),
+}
+
+impl Action {
+    pub const ALL: [Action; 20] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::SoftDrop,
+        Action::HardDrop,
+        Action::SonicDrop,
+        Action::RotateLeft,
+        Action::RotateRight,
+        Action::Rotate180,
+        Action::Hold,
+        Action::Pause,
+        Action::Restart,
+        Action::Quit,
+        Action::Hints,
+        Action::Undo,
+        Action::Redo,
+        Action::ToggleHud(1),
+        Action::ToggleHud(2),
+        Action::ToggleHud(3),
+        Action::ToggleHud(4),
+        Action::ToggleHud(5),
+    ];
+
+    /// The action's name in the keymap file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "move-left",
+            Action::MoveRight => "move-right",
+            Action::SoftDrop => "soft-drop",
+            Action::HardDrop => "hard-drop",
+            Action::SonicDrop => "sonic-drop",
+            Action::RotateLeft => "rotate-left",
+            Action::RotateRight => "rotate-right",
+            Action::Rotate180 => "rotate-180",
+            Action::Hold => "hold",
+            Action::Pause => "pause",
+            Action::Restart => "restart",
+            Action::Quit => "quit",
+            Action::Hints => "hints",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::ToggleHud(1) => "toggle-timer",
+            Action::ToggleHud(2) => "toggle-pps",
+            Action::ToggleHud(3) => "toggle-apm",
+            Action::ToggleHud(4) => "toggle-kpp",
+            Action::ToggleHud(_) => "toggle-lines",
+        }
+    }
+
+    /// Whether pressing the key for this action counts towards the keys per piece.
+    pub fn is_input(self) -> bool {
+        !matches!(
+            self,
+            Action::Pause
+                | Action::Restart
+                | Action::Quit
+                | Action::Hints
+                | Action::Undo
+                | Action::Redo
+                | Action::ToggleHud(_)
+        )
+    }
+}
+
+/// Bindings shared by every preset.
+const COMMON: [(&str, Action); 6] = [
+    ("ctrl-c", Action::Quit),
+    ("1", Action::ToggleHud(1)),
+    ("2", Action::ToggleHud(2)),
+    ("3", Action::ToggleHud(3)),
+    ("4", Action::ToggleHud(4)),
+    ("5", Action::ToggleHud(5)),
+];
+
+/// The game's original keys.
+const WASD: [(&str, Action); 18] = [
+    ("a", Action::MoveLeft),
+    ("left", Action::MoveLeft),
+    ("d", Action::MoveRight),
+    ("right", Action::MoveRight),
+    ("s", Action::SoftDrop),
+    ("down", Action::SoftDrop),
+    ("space", Action::HardDrop),
+    ("w", Action::RotateLeft),
+    ("up", Action::RotateLeft),
+    ("q", Action::RotateLeft),
+    ("e", Action::RotateRight),
+    ("c", Action::Hold),
+    ("p", Action::Pause),
+    ("r", Action::Restart),
+    ("z", Action::Quit),
+    ("h", Action::Hints),
+    ("u", Action::Undo),
+    ("y", Action::Redo),
+];
+
+const VIM: [(&str, Action); 15] = [
+    ("h", Action::MoveLeft),
+    ("l", Action::MoveRight),
+    ("j", Action::SoftDrop),
+    ("k", Action::HardDrop),
+    ("space", Action::SonicDrop),
+    ("d", Action::RotateLeft),
+    ("f", Action::RotateRight),
+    ("s", Action::Rotate180),
+    ("a", Action::Hold),
+    ("p", Action::Pause),
+    ("r", Action::Restart),
+    ("q", Action::Quit),
+    ("?", Action::Hints),
+    ("u", Action::Undo),
+    ("ctrl-r", Action::Redo),
+];
+
+/// The layout of the official guideline games.
+const GUIDELINE: [(&str, Action); 16] = [
+    ("left", Action::MoveLeft),
+    ("right", Action::MoveRight),
+    ("down", Action::SoftDrop),
+    ("space", Action::HardDrop),
+    ("up", Action::RotateRight),
+    ("x", Action::RotateRight),
+    ("z", Action::RotateLeft),
+    ("a", Action::Rotate180),
+    ("c", Action::Hold),
+    ("f1", Action::Pause),
+    ("p", Action::Pause),
+    ("r", Action::Restart),
+    ("q", Action::Quit),
+    ("h", Action::Hints),
+    ("u", Action::Undo),
+    ("y", Action::Redo),
+];
+
+pub const PRESETS: [(&str, &[(&str, Action)]); 3] =
+    [("wasd", &WASD), ("vim", &VIM), ("guideline", &GUIDELINE)];
+
+/// A keymap as written in `keys.toml`:
+///
+/// ```toml
+/// preset = "guideline"        # wasd (the default), vim or guideline
+///
+/// [keys]                      # replaces the preset's keys for these actions
+/// rotate-180 = "a"
+/// pause = ["p", "f1"]
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeymapFile {
+    preset: Option<String>,
+    #[serde(default)]
+    keys: HashMap<String, Keys>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Keys {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug)]
+pub enum KeymapError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeymapError::Io(e) => write!(f, "could not read keymap: {}", e),
+            KeymapError::Parse(e) => write!(f, "could not parse keymap: {}", e),
+            KeymapError::Invalid(e) => write!(f, "invalid keymap: {}", e),
+        }
+    }
+}
+
+/// Which action each key performs in a game.
+#[derive(Clone)]
+pub struct Keymap {
+    bindings: HashMap<Key, Action>,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::preset("wasd").unwrap()
+    }
+}
+
+/// Location of the keymap file, `tetris/keys.toml` in the user's config directory (`~/.config` unless
+/// `XDG_CONFIG_HOME` says otherwise).
+pub fn path() -> Option<PathBuf> {
+    let mut path = dirs::config_dir()?;
+    path.push("tetris");
+    path.push("keys.toml");
+    Some(path)
+}
+
+/// Loads the keymap file, or the default keymap if there is none.
+pub fn load() -> Result<Keymap, KeymapError> {
+    match path() {
+        Some(path) if path.exists() => {
+            parse(&std::fs::read_to_string(path).map_err(KeymapError::Io)?)
+        }
+        _ => Ok(Keymap::default()),
+    }
+}
+
+pub fn parse(text: &str) -> Result<Keymap, KeymapError> {
+    let file: KeymapFile = toml::from_str(text).map_err(KeymapError::Parse)?;
+    let preset = file.preset.as_deref().unwrap_or("wasd");
+    let mut keymap = Keymap::preset(preset)
+        .ok_or_else(|| KeymapError::Invalid(format!("unknown preset {}", preset)))?;
+
+    let mut bound: HashMap<Key, Action> = HashMap::new();
+    for (name, keys) in &file.keys {
+        let action = Action::ALL
+            .iter()
+            .copied()
+            .find(|a| a.name() == name)
+            .ok_or_else(|| KeymapError::Invalid(format!("unknown action {}", name)))?;
+        let keys = match keys {
+            Keys::One(key) => vec![key.clone()],
+            Keys::Many(keys) => keys.clone(),
+        };
+        keymap.bindings.retain(|_, a| *a != action);
+        for key in keys {
+            let key = Key::from_name(&key)
+                .ok_or_else(|| KeymapError::Invalid(format!("unknown key {}", key)))?;
+            if let Some(other) = bound.insert(key.clone(), action) {
+                return Err(KeymapError::Invalid(format!(
+                    "{} is bound to both {} and {}",
+                    key,
+                    other.name(),
+                    action.name()
+                )));
+            }
+        }
+    }
+    keymap.bindings.extend(bound);
+    Ok(keymap)
+}
+
+impl Keymap {
+    /// Returns the preset called `name`, if there is one.
+    pub fn preset(name: &str) -> Option<Keymap> {
+        let (_, keys) = PRESETS.iter().find(|(n, _)| *n == name)?;
+        let bindings = COMMON
+            .iter()
+            .chain(keys.iter())
+            .map(|&(key, action)| (Key::from_name(key).unwrap(), action))
+            .collect();
+        Some(Keymap { bindings })
+    }
+
+    pub fn action(&self, key: &Key) -> Option<Action> {
+        self.bindings.get(key).copied()
+    }
+
+    /// The names of the keys bound to `action`, sorted.
+    pub fn keys(&self, action: Action) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .bindings
+            .iter()
+            .filter(|(_, &a)| a == action)
+            .map(|(k, _)| k.to_string())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    /// Binds `key` to `action`, taking it from the action it was bound to before.
+    pub fn bind(&mut self, key: Key, action: Action) {
+        self.bindings.insert(key, action);
+    }
+
+    /// Removes every key of `action`.
+    pub fn unbind(&mut self, action: Action) {
+        self.bindings.retain(|_, a| *a != action);
+    }
+
+    /// Writes the keymap to the keymap file, listing the keys of every action. Returns the file's path.
+    pub fn save(&self) -> Result<PathBuf, KeymapError> {
+        let path = path().ok_or_else(|| KeymapError::Invalid("no config directory".to_string()))?;
+        let mut text = "[keys]\n".to_string();
+        for &action in Action::ALL.iter() {
+            let keys: Vec<String> = self
+                .keys(action)
+                .into_iter()
+                .map(|k| toml::Value::String(k).to_string())
+                .collect();
+            text += &format!("{} = [{}]\n", action.name(), keys.join(", "));
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(KeymapError::Io)?;
+        }
+        std::fs::write(&path, text).map_err(KeymapError::Io)?;
+        Ok(path)
+    }
+
+    /// Describes the keys of every action, one action per line.
+    pub fn describe(&self) -> Vec<String> {
+        Action::ALL
+            .iter()
+            .map(|&action| format!("{:<14}{}", action.name(), self.keys(action).join(", ")))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(text: &str) -> bool {
+        matches!(parse(text), Err(KeymapError::Invalid(_)))
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(Key::from_name("q"), Some(Key::Char('q')));
+        assert_eq!(Key::from_name("space"), Some(Key::Char(' ')));
+        assert_eq!(Key::from_name("F12"), Some(Key::F(12)));
+        assert_eq!(Key::from_name("ctrl-r"), Some(Key::Ctrl('r')));
+        assert_eq!(Key::from_name("shift-a"), Some(Key::Char('A')));
+        assert_eq!(
+            Key::from_name("alt-shift-up"),
+            Some(Key::Modified(
+                Modifiers::ALT.union(Modifiers::SHIFT),
+                Box::new(Key::Up)
+            ))
+        );
+        assert_eq!(
+            Key::from_name("ctrl--"),
+            Some(Key::Char('-').with(Modifiers::CTRL))
+        );
+        for name in [
+            "ctrl",
+            "alt",
+            "shift",
+            "ctrl-",
+            "alt-shift",
+            "f13",
+            "ctrl-nothing",
+        ] {
+            assert_eq!(Key::from_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn preset_with_overrides() {
+        let keymap = parse(
+            r#"
+            preset = "guideline"
+
+            [keys]
+            rotate-180 = "s"
+            pause = ["p", "f2"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(keymap.action(&Key::Char('s')), Some(Action::Rotate180));
+        assert_eq!(keymap.action(&Key::Char('a')), None);
+        assert_eq!(keymap.keys(Action::Pause), vec!["f2", "p"]);
+        assert_eq!(keymap.action(&Key::Char('c')), Some(Action::Hold));
+        assert_eq!(keymap.action(&Key::Ctrl('c')), Some(Action::Quit));
+    }
+
+    #[test]
+    fn empty_file_is_the_default_preset() {
+        assert_eq!(parse("").unwrap().describe(), Keymap::default().describe());
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert!(invalid("preset = \"emacs\""));
+        assert!(invalid("[keys]\nfly = \"x\""));
+        assert!(invalid("[keys]\npause = \"ctrl\""));
+        assert!(invalid("[keys]\npause = [\"p\", \"nothing\"]"));
+        assert!(matches!(parse("keys = 1"), Err(KeymapError::Parse(_))));
+        assert!(matches!(
+            parse("colour = \"red\""),
+            Err(KeymapError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn key_bound_twice() {
+        let error = parse("[keys]\nhold = \"x\"\npause = [\"p\", \"x\"]")
+            .err()
+            .unwrap();
+        assert!(
+            error.to_string().contains("x is bound to both"),
+            "{}",
+            error
+        );
+    }
+}
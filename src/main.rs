@@ -1,594 +1,450 @@
 extern crate rand;
 
+mod ai;
+mod board;
+mod crossterm_display;
 mod display;
+mod editor;
+mod effects;
+mod finesse;
+mod fumen;
+mod game;
+mod hud;
+mod input;
+mod keymap;
+mod menu;
+mod opener;
 mod piece;
+mod puzzle;
+mod render;
+mod rules;
 mod scores;
+mod settings;
+mod snapshot;
+mod solver;
+mod stats;
+mod text_display;
+mod theme;
+mod tune;
 mod util;
 
-use clap::clap_app;
+use board::*;
+use clap::{clap_app, value_t, ArgMatches};
+use crossterm_display::CrosstermDisplay;
 use display::Display;
-use piece::*;
+use game::{Game, Mode};
+use menu::{Choice, Menu};
+use render::{CellStyle, Frame, Renderer};
+use settings::Settings;
 use std::cell::RefCell;
 use std::io::stdout;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::mpsc;
-use std::sync::Arc;
-use std::thread;
-use std::time::{Duration};
 use termion::raw::IntoRawMode;
 use termion::screen::AlternateScreen;
-use util::*;
-
-const BOARD_WIDTH: u32 = 10;
-const BOARD_HEIGHT: u32 = 20;
-const HIDDEN_ROWS: u32 = 2;
-
-enum Key {
-    Up,
-    Down,
-    Left,
-    Right,
-    Space,
-    CtrlC,
-    Hold,
-    Pause,
-    Char(char),
-}
-
-enum GameUpdate {
-    KeyPress(Key),
-    Tick,
-}
-
-#[derive(Debug, Copy, Clone)]
-struct Point {
-    x: i32,
-    y: i32,
-}
+use text_display::TextDisplay;
 
-struct Board {
-    cells: [[Option<Color>; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize],
-}
+fn main() {
+    let matches = clap_app!(Tetris =>
+        (version: env!("CARGO_PKG_VERSION"))
+        (author: "royalmustard <royalmustard@memium.de>")
+        (about: "Tetris (but its big stonks)")
+        (@arg SCORES: -s --scores "Print highscores")
+        (@arg FINESSE: -f --finesse "Finesse training: shows the optimal inputs after every finesse fault")
+        (@arg OPENER: -o --opener +takes_value "Opener training: drills the named opener (TSD, PCO)")
+        (@arg PUZZLE: --puzzle +takes_value "Plays a puzzle file, or a built-in puzzle (tetris, tsd, pc)")
+        (@arg RESUME: --resume "Resumes the marathon game saved when you last quit")
+        (@arg RULES: --rules +takes_value
+            "Rule set file, or a built-in rule set (classic, guideline, modern); a resumed game keeps its own")
+        (@arg NO_ANIMATIONS: --("no-animations") "Turns off the lock and line clear animations, for competitive play")
+        (@arg THEME: --theme +takes_value "Colour theme file, or a built-in theme (classic, vivid, mono)")
+        (@arg STYLE: --style +takes_value possible_value[blocks solid brackets small]
+            "How cells are drawn; small fits two rows of the playfield on each line (default blocks)")
+        (@arg BOX: --box "Draws the borders with box-drawing characters")
+        (@arg BACKEND: --backend +takes_value possible_value[termion crossterm]
+            "Terminal library the game is drawn with (default termion)")
+        (@arg FUMEN: --fumen "Prints the final board and the whole game as fumen strings when the game ends")
+        (@subcommand edit =>
+            (about: "Edits a board and saves it as a puzzle (.toml) or a fumen (any other file)")
+            (@arg FILE: "File to open and save (default puzzle.toml)")
+        )
+        (@subcommand keys =>
+            (about: "Prints the key bindings and the file they are configured in")
+        )
+        (@subcommand show =>
+            (about: "Prints the board of a puzzle or a fumen as text")
+            (@arg BOARD: +required "Puzzle file, built-in puzzle or fumen string")
+        )
+        (@subcommand tune =>
+            (about: "Evolves the bot's evaluation weights through headless self-play")
+            (@arg POPULATION: -p --population +takes_value {|v| at_least(&v, 2)} "Weight sets per generation (default 50)")
+            (@arg GENERATIONS: -g --generations +takes_value {|v| at_least(&v, 1)} "Number of generations (default 20)")
+            (@arg GAMES: -n --games +takes_value {|v| at_least(&v, 1)} "Games per weight set and generation (default 8)")
+            (@arg PIECES: --pieces +takes_value {|v| at_least(&v, 1)} "Maximum pieces per game (default 500)")
+            (@arg THREADS: -j --threads +takes_value {|v| at_least(&v, 1)} "Worker threads (default: all cores)")
+            (@arg SEED: --seed +takes_value "Seed for the tuner and its games")
+            (@arg OUT: -o --out +takes_value "File the best weights are written to (default ~/.tetris_weights)")
+        )
+    )
+    .get_matches();
 
-impl Board {
-    pub fn render(&self, display: &mut Display) {
-        for y in HIDDEN_ROWS..BOARD_HEIGHT {
-            display.set_text("|", 0, y, Color::Red, Color::Black);
-            display.set_text("|", BOARD_WIDTH * 2 + 1, y, Color::Red, Color::Black);
-        }
-        for x in 0..(BOARD_WIDTH * 2 + 1) {
-            display.set_text("-", x, BOARD_HEIGHT, Color::Red, Color::Black);
-        }
-        for row in 0..BOARD_HEIGHT {
-            for col in 0..BOARD_WIDTH {
-                match self.cells[row as usize][col as usize] {
-                    Some(color) => {
-                        let c = 1 + (col * 2);
-                        display.set_text(" ", c, row, color, color);
-                        display.set_text(" ", c + 1, row, color, color);
-                    }
-                    None => (),
-                }
-            }
-        }
+    if matches.is_present("SCORES") {
+        let modes: Vec<&str> = Mode::SCORED.iter().map(|m| m.name()).collect();
+        scores::print_highscores(&modes);
+        return;
     }
 
-    pub fn lock_piece(&mut self, piece: &Piece, origin: Point) {
-        piece.each_point(&mut |row, col| {
-            let x = origin.x + (col as i32);
-            let y = origin.y + (row as i32);
-            self.cells[y as usize][x as usize] = Some(piece.color);
+    if let Some(m) = matches.subcommand_matches("tune") {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        tune::run(&tune::TuneConfig {
+            population: arg_or(m, "POPULATION", 50),
+            generations: arg_or(m, "GENERATIONS", 20),
+            games: arg_or(m, "GAMES", 8),
+            max_pieces: arg_or(m, "PIECES", 500),
+            threads: arg_or(m, "THREADS", threads).max(1),
+            seed: arg_or(m, "SEED", rand::random()),
+            out: m.value_of("OUT").map_or_else(ai::weights_path, Into::into),
         });
+        return;
     }
 
-    pub fn collision_test(&self, piece: &Piece, origin: Point) -> bool {
-        let mut found = false;
-        piece.each_point(&mut |row, col| {
-            if !found {
-                let x = origin.x + col;
-                let y = origin.y + row;
-                if x < 0
-                    || x >= (BOARD_WIDTH as i32)
-                    || y < 0
-                    || y >= (BOARD_HEIGHT as i32)
-                    || self.cells[y as usize][x as usize] != None
-                {
-                    found = true;
-                }
-            }
-        });
-
-        found
+    // Command line options override the settings picked in the menu
+    let mut settings = Settings::load();
+    if let Some(rules) = matches.value_of("RULES") {
+        settings.rules = rules.to_string();
     }
-
-    /// Clears the board of any complete lines, shifting down rows to take their place.
-    /// Returns the total number of lines that were cleared.
-    fn clear_lines(&mut self) -> u32 {
-        let mut cleared_lines: usize = 0;
-        for row in (0..self.cells.len()).rev() {
-            if (row as i32) - (cleared_lines as i32) < 0 {
-                break;
-            }
-
-            if cleared_lines > 0 {
-                self.cells[row] = self.cells[row - cleared_lines];
-                self.cells[row - cleared_lines] = [None; BOARD_WIDTH as usize];
-            }
-
-            while !self.cells[row].iter().any(|x| *x == None) {
-                cleared_lines += 1;
-                self.cells[row] = self.cells[row - cleared_lines];
-                self.cells[row - cleared_lines] = [None; BOARD_WIDTH as usize];
-            }
-        }
-
-        cleared_lines as u32
+    if let Some(theme) = matches.value_of("THEME") {
+        settings.theme = theme.to_string();
     }
-}
-
-struct Game {
-    board: Board,
-    piece_bag: PieceBag,
-    piece: Piece,
-    hold: Option<Piece>,
-    piece_position: Point,
-    score: u32,
-    switched: bool,
-    level: u32,
-    speed: Arc<AtomicU64>,
-    to_clear: i32,
-    paused: Arc<AtomicBool>,
-    cleared_last_round: u32,
-    combo_counter: u32,
-}
+    if let Some(style) = matches.value_of("STYLE") {
+        settings.style = style.to_string();
+    }
+    settings.box_borders |= matches.is_present("BOX");
+    settings.animations &= !matches.is_present("NO_ANIMATIONS");
 
-impl Game {
-    fn new() -> Game {
-        let mut piece_bag = PieceBag::new();
-        let piece = piece_bag.pop();
-
-        let mut game = Game {
-            board: Board {
-                cells: [[None; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize],
-            },
-            piece_bag: piece_bag,
-            piece: piece,
-            hold: None,
-            piece_position: Point { x: 0, y: 0 },
-            score: 0,
-            switched: false,
-            level: 1,
-            speed: Arc::new(AtomicU64::new(500)),
-            to_clear: 10,
-            paused: Arc::new(AtomicBool::new(false)),
-            cleared_last_round: 0,
-            combo_counter: 0,
-        };
+    let theme = match theme::load(&settings.theme) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
 
-        game.place_new_piece();
-        game
-    }
+    let rules = match rules::load(&settings.rules) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
 
-    /// Returns the new position of the current piece if it were to be dropped.
-    fn find_dropped_position(&self) -> Point {
-        let mut origin = self.piece_position;
-        while !self.board.collision_test(&self.piece, origin) {
-            origin.y += 1;
+    let keymap = match keymap::load() {
+        Ok(k) => k,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
         }
-        origin.y -= 1;
-        origin
+    };
+    if matches.subcommand_matches("keys").is_some() {
+        match keymap::path() {
+            Some(path) => println!("Key bindings (configured in {}):", path.display()),
+            None => println!("Key bindings:"),
+        }
+        keymap.describe().iter().for_each(|l| println!("  {}", l));
+        return;
     }
 
-    /// Draws the game to the display.
-    fn render(&self, display: &mut Display) {
-        // Render the board
-        self.board.render(display);
-
-        // Render the level
-        let left_margin = BOARD_WIDTH * 2 + 5;
-        display.set_text(
-            format!("Level: {}", self.level),
-            left_margin,
-            3,
-            Color::Red,
-            Color::Black,
-        );
-
-        //render score
-        display.set_text(
-            format!("Score: {}", self.score),
-            left_margin,
-            5,
-            Color::Red,
-            Color::Black,
-        );
-
-        //render combo
-        display.set_text(
-            format!("Combo: {}", self.combo_counter),
-            left_margin,
-            7,
-            Color::Red,
-            Color::Black,
-        );
-        // Render a ghost piece
-        let x = 1 + (2 * self.piece_position.x);
-        let ghost_position = self.find_dropped_position();
-        self.render_piece(
-            display,
-            &self.piece,
-            Point {
-                x: x,
-                y: ghost_position.y,
-            },
-            true,
-        );
-
-        // Render the currently falling piece
-        self.render_piece(
-            display,
-            &self.piece,
-            Point {
-                x: x,
-                y: self.piece_position.y,
-            },
-            false,
-        );
-
-        // Render the next piece
-        display.set_text("Next piece:", left_margin, 9, Color::Red, Color::Black);
-        let next_piece = self.piece_bag.peek();
-        self.render_piece(
-            display,
-            &next_piece,
-            Point {
-                x: (left_margin as i32) + 2,
-                y: 11,
-            },
-            false,
-        );
-
-        // Render hold piece
-        display.set_text("Holding:", left_margin, 13, Color::Red, Color::Black);
-        if let Some(p) = &self.hold {
-            self.render_piece(
-                display,
-                &p,
-                Point {
-                    x: (left_margin as i32) + 2,
-                    y: 15,
-                },
-                false,
-            );
+    let mut look = Look {
+        theme,
+        style: CellStyle::from_name(&settings.style).unwrap_or(CellStyle::Blocks),
+        box_borders: settings.box_borders,
+    };
+    let crossterm = matches.value_of("BACKEND") == Some("crossterm");
+
+    if let Some(m) = matches.subcommand_matches("show") {
+        match show(m.value_of("BOARD").unwrap()) {
+            Ok(text) => print!("{}", text),
+            Err(e) => eprintln!("{}", e),
         }
+        return;
     }
 
-    fn render_piece(&self, display: &mut Display, piece: &Piece, origin: Point, ghost: bool) {
-        let color = piece.color;
-
-        piece.each_point(&mut |row, col| {
-            let x = (origin.x + 2 * col) as u32;
-            let y = (origin.y + row) as u32;
-            if ghost {
-                display.set_text(
-                    " ",
-                    x,
-                    y,
-                    piece.get_shadow_color(),
-                    piece.get_shadow_color(),
-                );
-                display.set_text(
-                    " ",
-                    x + 1,
-                    y,
-                    piece.get_shadow_color(),
-                    piece.get_shadow_color(),
-                );
-            } else {
-                display.set_text(" ", x, y, color, color);
-                display.set_text(" ", x + 1, y, color, color);
+    if let Some(m) = matches.subcommand_matches("edit") {
+        let mut editor = match editor::Editor::open(m.value_of("FILE").unwrap_or("puzzle.toml")) {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
             }
-        });
-    }
-
-    /// Moves the current piece in the specified direction. Returns true if the piece could be moved and
-    /// didn't collide.
-    fn move_piece(&mut self, x: i32, y: i32) -> bool {
-        let new_position = Point {
-            x: self.piece_position.x + x,
-            y: self.piece_position.y + y,
         };
-        if self.board.collision_test(&self.piece, new_position) {
-            false
-        } else {
-            self.piece_position = new_position;
-            true
-        }
+        editor.run(&mut Terminal::open(&look, crossterm));
+        println!("Fumen: {}", editor.fumen());
+        return;
     }
 
-    /// Rotates the current piece in the specified direction. Returns true if the piece could be rotated
-    /// without any collisions.
-    fn rotate_piece(&mut self, direction: Direction) -> bool {
-        let mut new_piece = self.piece.clone();
-        new_piece.rotate(direction);
-
-        if self.board.collision_test(&new_piece, self.piece_position) {
-            let mut new_position = self.piece_position.clone();
-            if self.piece_position.x < (BOARD_WIDTH / 2) as i32
-            //wallkick left
-            {
-                new_position.x += 1;
-            } else {
-                new_position.x -= 1;
+    let puzzle = match matches.value_of("PUZZLE").map(puzzle::load) {
+        Some(Ok(p)) => Some(p),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            return;
+        }
+        None => None,
+    };
+
+    let resume = if matches.is_present("RESUME") {
+        match snapshot::load(&snapshot::path()) {
+            Ok(s) => Some(s),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    } else {
+        None
+    };
+
+    let mode = if puzzle.is_some() {
+        Mode::Puzzle
+    } else if let Some(name) = matches.value_of("OPENER") {
+        match opener::find(name) {
+            Some(i) => Mode::Opener(i),
+            None => {
+                let names: Vec<&str> = opener::OPENERS.iter().map(|o| o.name).collect();
+                eprintln!("Unknown opener {}. Available: {}", name, names.join(", "));
+                return;
             }
-            if self.board.collision_test(&new_piece, new_position) {
-                if self.piece_position.x > (BOARD_WIDTH / 2) as i32
-                //wallkick right
-                {
-                    new_position.x -= 1;
-                    if self.board.collision_test(&new_piece, new_position) {
-                        return false;
+        }
+    } else if matches.is_present("FINESSE") {
+        Mode::Finesse
+    } else {
+        Mode::Marathon
+    };
+
+    if puzzle.is_none() && resume.is_none() && mode == Mode::Marathon {
+        let mut menu = Menu::new(settings, keymap);
+        loop {
+            // The terminal is reopened for every game, so that the look chosen in the menu applies to it
+            let (game, resumed) = {
+                let terminal = &mut Terminal::open(&look, crossterm);
+                match menu.run(terminal) {
+                    Choice::Play { mut game, resumed } => {
+                        game.play(terminal);
+                        record_score(&game, terminal, &mut menu.settings.name);
+                        (game, resumed)
                     }
+                    Choice::Quit => return,
                 }
+            };
+            menu.settings.hud = game.hud;
+            menu.report(finish(&game, resumed, matches.is_present("FUMEN")));
+            // Settings changed in the menu apply from the next game on
+            if let Ok(theme) = theme::load(&menu.settings.theme) {
+                look.theme = theme;
             }
-
-            self.piece = new_piece;
-            self.piece_position = new_position;
-            return true;
-        } else {
-            self.piece = new_piece;
-            true
+            look.style = CellStyle::from_name(&menu.settings.style).unwrap_or(CellStyle::Blocks);
+            look.box_borders = menu.settings.box_borders;
         }
     }
 
-    /// Switches the current piece with the held piece
-    /// Places a new piece when hold was empty previously
-    fn switch_hold(&mut self) -> bool {
-        if self.switched {
-            return false;
+    let resumed = resume.is_some();
+    let animations = settings.animations;
+    let hud = settings.hud;
+    let mut name = settings.name;
+    let (send, recv) = std::sync::mpsc::channel();
+
+    let handle = std::thread::spawn(move || {
+        let mut game = match (puzzle, resume) {
+            (Some(p), _) => Game::with_puzzle(p),
+            (None, Some(s)) => Game::from_snapshot(s),
+            (None, None) => Game::new(mode),
+        };
+        if !resumed {
+            game.rules = rules;
         }
-        if let Some(p) = &self.hold {
-            let tmp = p.clone();
-            self.hold = Some(self.piece.clone());
-            self.piece = tmp;
-        } else {
-            self.hold = Some(self.piece.clone());
-            self.piece = self.piece_bag.pop();
+        game.hud = hud;
+        game.keymap = keymap;
+        game.animations = animations;
+        let terminal = &mut Terminal::open(&look, crossterm);
+        game.play(terminal);
+        record_score(&game, terminal, &mut name);
+        send.send(game).unwrap();
+    });
+
+    if let Ok(game) = recv.recv() {
+        handle.join().unwrap();
+        for line in finish(&game, resumed, matches.is_present("FUMEN")) {
+            println!("{}", line);
         }
-        self.switched = true;
-        return self.place_new_piece();
     }
+}
 
-    ///Pauses or unpauses the game
-    fn pause(&self) -> bool {
-        let p = self.paused.load(Ordering::SeqCst);
-        self.paused.store(!p, Ordering::SeqCst);
-        true
+/// Whether the score of `game` goes on the leaderboard.
+fn is_scored(game: &Game) -> bool {
+    // A quit marathon game only counts once it is finished, and opener training restarts the board on
+    // every mistake, so its score means nothing.
+    game.puzzle.is_none()
+        && !(game.mode == Mode::Marathon && game.quit)
+        && !matches!(game.mode, Mode::Opener(_))
+        && game.undos_used == 0
+}
+
+/// Records the score of `game` if it makes the leaderboard, asking for the name on `terminal`. `name` is
+/// offered as the name and remembered as the one entered.
+fn record_score(game: &Game, terminal: &mut Terminal, name: &mut String) {
+    if !is_scored(game) {
+        return;
     }
+    scores::manage_highscore(terminal, game, name);
+    // Only the name is saved, so that command line options don't become settings
+    let _ = Settings::update(|settings| settings.name = name.clone());
+}
 
-    /// Positions the current piece at the top of the board. Returns true if the piece can be placed without
-    /// any collisions.
-    fn place_new_piece(&mut self) -> bool {
-        let origin = Point {
-            x: ((BOARD_WIDTH - (self.piece.shape.len() as u32)) / 2) as i32,
-            y: 0,
-        };
-        if self.board.collision_test(&self.piece, origin) {
-            false
-        } else {
-            self.piece_position = origin;
-            true
+/// Saves `game` if the player quit a marathon game, and returns the lines that report how it went.
+fn finish(game: &Game, resumed: bool, fumen: bool) -> Vec<String> {
+    let mut report = Vec::new();
+    if game.mode == Mode::Finesse {
+        report.push("Finesse faults per piece:".to_string());
+        report.extend(game.finesse.report());
+    }
+    if fumen {
+        report.push(format!("Final board: {}", fumen::encode_board(&game.board)));
+        report.push(format!("Game: {}", fumen::encode(&game.history)));
+    }
+    if let Some(state) = &game.puzzle {
+        match state.solved {
+            Some(true) => report.push(format!("Puzzle solved: {}", state.puzzle.name)),
+            _ => report.push(format!("Puzzle failed: {}", state.puzzle.name)),
         }
+        return report;
     }
-
-    /// Advances the game by moving the current piece down one step. If the piece cannot move down, the piece
-    /// is locked and the game is set up to drop the next piece.  Returns true if the game could be advanced,
-    /// false if the player has lost.
-    fn advance_game(&mut self) -> bool {
-        if !self.move_piece(0, 1) {
-            self.board.lock_piece(&self.piece, self.piece_position);
-            let cleared = self.board.clear_lines();
-            if self.cleared_last_round > 0 && cleared > 0 {
-                self.combo_counter += 1;
-            } else {
-                self.combo_counter = 0;
-            }
-            self.score += 100 * self.combo_counter;
-            match cleared {
-                1 => self.score += 100 * self.level,
-                2 => self.score += 300 * self.level,
-                3 => self.score += 500 * self.level,
-                4 => self.score += 800 * self.level,
-                _ => (),
-            }
-            self.cleared_last_round = cleared;
-            self.to_clear -= cleared as i32;
-            if self.to_clear <= 0 {
-                self.level += 1;
-                self.to_clear = self.level as i32 * 10;
-                let new_speed = 500 - (self.level - 1) * 10;
-
-                self.speed.store(new_speed as u64, Ordering::SeqCst);
-            }
-            self.piece = self.piece_bag.pop();
-            self.switched = false;
-            if !self.place_new_piece() {
-                return false;
-            }
+    // Quitting a marathon game saves it, and its score only counts once it is finished.
+    if game.mode == Mode::Marathon {
+        let path = snapshot::path();
+        if game.quit {
+            report.push(match game.snapshot().save(&path) {
+                Ok(()) => "Game saved. Continue it from the menu or with --resume.".to_string(),
+                Err(e) => format!("Could not save the game: {}", e),
+            });
+            return report;
+        } else if resumed {
+            let _ = std::fs::remove_file(&path);
         }
-
-        true
     }
-
-    /// Drops the current piece to the lowest spot on the board where it fits without collisions and
-    /// advances the game.
-    fn drop_piece(&mut self) -> bool {
-        while self.move_piece(0, 1) {}
-        self.advance_game()
+    if game.undos_used > 0 && !matches!(game.mode, Mode::Opener(_)) {
+        report.push("Undo was used, so this game is not eligible for the leaderboard.".to_string());
     }
+    report
+}
 
-    fn keypress(&mut self, key: Key) {
-        if self.paused.load(Ordering::SeqCst) {
-            match key {
-                Key::Pause => self.pause(),
-                _ => false,
-            };
-            return;
-        }
-        match key {
-            Key::Left => self.move_piece(-1, 0),
-            Key::Right => self.move_piece(1, 0),
-            Key::Down => self.advance_game(),
-            Key::Up => self.rotate_piece(Direction::Left),
-            Key::Space => self.drop_piece(),
-            Key::Hold => self.switch_hold(),
-            Key::Pause => self.pause(),
-            Key::Char('q') => self.rotate_piece(Direction::Left),
-            Key::Char('e') => self.rotate_piece(Direction::Right),
-            _ => false,
+/// How the game is drawn on the terminal.
+struct Look {
+    theme: theme::Theme,
+    style: CellStyle,
+    box_borders: bool,
+}
+
+const FRAME_WIDTH: u32 = BOARD_WIDTH * 2 + 46;
+const FRAME_HEIGHT: u32 = BOARD_HEIGHT + 2;
+
+/// The terminal the game is drawn on, through whichever library was picked with `--backend`.
+enum Terminal {
+    Termion(Display),
+    Crossterm(CrosstermDisplay),
+}
+
+impl Terminal {
+    /// Opens a display on the alternate screen, with the terminal in raw mode until it is dropped.
+    fn open(look: &Look, crossterm: bool) -> Terminal {
+        let mut terminal = if crossterm {
+            Terminal::Crossterm(CrosstermDisplay::new(FRAME_WIDTH, FRAME_HEIGHT).unwrap())
+        } else {
+            Terminal::Termion(Display::new(
+                FRAME_WIDTH,
+                FRAME_HEIGHT,
+                RefCell::new(Box::new(AlternateScreen::from(
+                    stdout().into_raw_mode().unwrap(),
+                ))),
+                None,
+            ))
         };
+        let frame = terminal.frame_mut();
+        frame.theme = look.theme.clone();
+        frame.cell_style = look.style;
+        frame.box_borders = look.box_borders;
+        terminal
     }
+}
 
-    fn play(&mut self, display: &mut Display) {
-        let (tx_event, rx_event) = mpsc::channel();
-        // Spawn a thread which sends periodic game ticks to advance the piece
-        {
-            let tx_event = tx_event.clone();
-            let arc = self.speed.clone();
-            let p = self.paused.clone();
-            thread::spawn(move || {
-                loop {
-                    let dur = Duration::from_millis(arc.load(Ordering::SeqCst));
-                    thread::sleep(dur);
-                    if !p.load(Ordering::SeqCst) {
-                        if let Ok(_) = tx_event.send(GameUpdate::Tick) {
-                        } else {
-                            break;
-                        }
-                    }
-                }
-            });
+impl Renderer for Terminal {
+    fn frame(&self) -> &Frame {
+        match self {
+            Terminal::Termion(display) => display.frame(),
+            Terminal::Crossterm(display) => display.frame(),
         }
+    }
 
-        // Spawn a thread which listens for keyboard input
-        let tx_event = tx_event.clone();
-        let (flag, control) = thread_control::make_pair();
-
-        let input_handle = thread::spawn(move || {
-            let stdin = &mut std::io::stdin();
+    fn frame_mut(&mut self) -> &mut Frame {
+        match self {
+            Terminal::Termion(display) => display.frame_mut(),
+            Terminal::Crossterm(display) => display.frame_mut(),
+        }
+    }
 
-            while flag.alive() {
-                if let Ok(_) = match get_input(stdin) {
-                    Some(k) => tx_event.send(GameUpdate::KeyPress(k)),
-                    None => Ok(()),
-                } {
-                } else {
-                    break;
-                }
-            }
-        });
+    fn render(&mut self) {
+        match self {
+            Terminal::Termion(display) => display.render(),
+            Terminal::Crossterm(display) => display.render(),
+        }
+    }
 
-        // Main game loop. The loop listens and responds to timer and keyboard updates received on a channel
-        // as sent by the threads spawned above.
-        loop {
-            display.clear_buffer();
-            self.render(display);
-            display.render();
-
-            match rx_event.recv() {
-                Ok(update) => {
-                    match update {
-                        GameUpdate::KeyPress(key) => {
-                            match key {
-                                Key::Char('z') | Key::CtrlC => break,
-                                k => {
-                                    self.keypress(k);
-                                }
-                            };
-                        }
-                        GameUpdate::Tick => {
-                            if !self.advance_game() {
-                                break;
-                            }
-                        }
-                    };
-                }
-                Err(err) => panic!("{}", err),
-            }
+    fn fits(&self) -> bool {
+        match self {
+            Terminal::Termion(display) => display.fits(),
+            Terminal::Crossterm(display) => display.fits(),
         }
-        control.stop();
-        input_handle.join().unwrap(); //to prevent input thread from eating input
     }
 }
 
-fn get_input(stdin: &mut std::io::Stdin) -> Option<Key> {
-    use std::io::Read;
-
-    let c = &mut [0u8];
-    match stdin.read(c) {
-        Ok(_) => {
-            match std::str::from_utf8(c) {
-                Ok("w") => Some(Key::Up),
-                Ok("a") => Some(Key::Left),
-                Ok("s") => Some(Key::Down),
-                Ok("d") => Some(Key::Right),
-                Ok(" ") => Some(Key::Space),
-                Ok("c") => Some(Key::Hold),
-                Ok("z") => Some(Key::CtrlC),
-                Ok("p") => Some(Key::Pause),
-                // Escape sequence started - must read two more bytes.
-                Ok("\x1b") => {
-                    let code = &mut [0u8; 2];
-                    match stdin.read(code) {
-                        Ok(_) => match std::str::from_utf8(code) {
-                            Ok("[A") => Some(Key::Up),
-                            Ok("[B") => Some(Key::Down),
-                            Ok("[C") => Some(Key::Right),
-                            Ok("[D") => Some(Key::Left),
-                            _ => None,
-                        },
-                        Err(msg) => panic!("could not read from standard in: {}", msg),
-                    }
-                }
-                Ok(n) => Some(Key::Char(n.chars().next().unwrap())),
-                _ => None,
+/// Draws the board of a puzzle, or of the first page of a fumen, as text.
+fn show(board: &str) -> Result<String, String> {
+    let display = &mut TextDisplay::new(BOARD_WIDTH * 2 + 2, BOARD_HEIGHT + 1);
+    if board.trim().get(1..5) == Some("115@") {
+        let page = fumen::decode(board)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .next()
+            .ok_or("the fumen has no pages")?;
+        page.board.render(display);
+        if let Some((piece, origin)) = page.piece {
+            for cell in piece.cells(origin) {
+                draw_cell(display, cell, piece.color);
             }
         }
-        Err(msg) => panic!("could not read from standard in: {}", msg),
+    } else {
+        puzzle::load(board)
+            .map_err(|e| e.to_string())?
+            .board
+            .render(display);
     }
+    display.render();
+    Ok(display.text().to_string())
 }
 
-fn main() {
-    let matches = clap_app!(Tetris =>
-        (version: "1.0")
-        (author: "royalmustard <royalmustard@memium.de>")
-        (about: "Tetris (but its big stonks)")
-        (@arg SCORES: -s --scores "Print highscores")
-    )
-    .get_matches();
-
-    if matches.is_present("SCORES") {
-        scores::print_highscores();
-        return;
+/// Checks that an argument is a whole number no smaller than `min`.
+fn at_least(value: &str, min: usize) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(n) if n >= min => Ok(()),
+        Ok(_) => Err(format!("must be at least {}", min)),
+        Err(_) => Err(format!("'{}' is not a whole number", value)),
     }
+}
 
-    let (send, recv) = std::sync::mpsc::channel();
-
-    let handle = std::thread::spawn(move || {
-        let display = &mut Display::new(
-            BOARD_WIDTH * 2 + 100,
-            BOARD_HEIGHT + 2,
-            RefCell::new(Box::new(AlternateScreen::from(
-                stdout().into_raw_mode().unwrap(),
-            ))),
-        );
-        let game = &mut Game::new();
-        game.play(display);
-        send.send(game.score).unwrap();
-    });
-
-    if let Ok(score) = recv.recv() {
-        handle.join().unwrap();
-        scores::manage_highscore(score);
+/// Parses the value of argument `name`, exiting with clap's usual error if it is malformed.
+fn arg_or<T>(matches: &ArgMatches, name: &str, default: T) -> T
+where
+    T: std::str::FromStr,
+{
+    if matches.is_present(name) {
+        value_t!(matches, name, T).unwrap_or_else(|e| e.exit())
+    } else {
+        default
     }
 }
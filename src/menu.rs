@@ -0,0 +1,592 @@
+use crate::game::{Game, Mode};
+use crate::input;
+use crate::keymap::{Action, Key, Keymap};
+use crate::opener::OPENERS;
+use crate::puzzle;
+use crate::render::{CellStyle, Renderer};
+use crate::rules;
+use crate::scores;
+use crate::settings::Settings;
+use crate::snapshot;
+use crate::theme;
+use crate::util::Color;
+use std::time::Duration;
+
+/// How long the menu waits for a key before it is drawn again, so that it follows terminal resizes.
+const REDRAW: Duration = Duration::from_millis(100);
+
+const MAX_LEVEL: u32 = 30;
+
+/// Seeds of up to this many digits always fit in a u64.
+const SEED_DIGITS: usize = 19;
+
+const STYLES: [&str; 4] = ["blocks", "solid", "brackets", "small"];
+
+const HUD_FIELDS: [&str; 5] = ["Timer", "PPS", "APM", "KPP", "Lines"];
+
+const HELP: &str = "Arrows: choose  Enter: select  Esc: back";
+
+const REPORT_HELP: &str = "Up/Down: scroll  Enter/Esc: back";
+
+const KEYS_HELP: &str = "Enter: add a key  Backspace: clear  Esc: back";
+
+/// A game that can be started from the menu.
+#[derive(Clone, Copy)]
+enum Play {
+    Mode(Mode),
+    /// A built-in puzzle.
+    Puzzle(&'static str),
+}
+
+impl Play {
+    fn name(self) -> String {
+        match self {
+            Play::Mode(Mode::Opener(i)) => format!("Opener {}", OPENERS[i].name),
+            Play::Mode(mode) => mode.name().to_string(),
+            Play::Puzzle(name) => format!("Puzzle {}", name),
+        }
+    }
+}
+
+/// The lines of the main screen.
+#[derive(Clone, Copy, PartialEq)]
+enum Item {
+    Mode,
+    Level,
+    Seed,
+    Start,
+    /// Only shown if there is a saved game.
+    Continue,
+    Leaderboards,
+    Settings,
+    Keys,
+    Quit,
+}
+
+/// The lines of the settings screen.
+#[derive(Clone, Copy)]
+enum Setting {
+    Rules,
+    Theme,
+    Style,
+    Box,
+    Animations,
+    /// The sidebar field with this index, as toggled in game.
+    Hud(u32),
+}
+
+const SETTINGS: [Setting; 10] = [
+    Setting::Rules,
+    Setting::Theme,
+    Setting::Style,
+    Setting::Box,
+    Setting::Animations,
+    Setting::Hud(1),
+    Setting::Hud(2),
+    Setting::Hud(3),
+    Setting::Hud(4),
+    Setting::Hud(5),
+];
+
+enum Screen {
+    Main,
+    Leaderboards,
+    Settings,
+    Keys,
+    /// Waiting for a key to bind to the selected action.
+    Rebind,
+    /// How the last game went.
+    Report,
+}
+
+/// What the menu keys do. Besides the arrows, Enter and Esc, the game's own keys work: moving left and
+/// right, soft drop for down, rotating for up, hard drop to select and quit to go back.
+enum Nav {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Back,
+}
+
+/// What the player picked in the menu.
+pub enum Choice {
+    Play { game: Box<Game>, resumed: bool },
+    Quit,
+}
+
+/// The main menu: picks the mode, start level and seed of the next game, and shows the leaderboards,
+/// settings and key bindings.
+pub struct Menu {
+    pub settings: Settings,
+    pub keymap: Keymap,
+    screen: Screen,
+    plays: Vec<Play>,
+    play: usize,
+    level: u32,
+    /// The digits typed for the seed. Games get a random seed if there are none.
+    seed: String,
+    item: Item,
+    /// Index into `Mode::SCORED` of the leaderboard shown.
+    leaderboard: usize,
+    /// The lines of that leaderboard, read when it is picked rather than on every redraw.
+    scores: Result<Vec<String>, String>,
+    setting: usize,
+    /// Index into `Action::ALL` of the action selected on the keys screen.
+    action: usize,
+    message: String,
+    /// The lines reporting how the last game went, and the first one shown.
+    report: Vec<String>,
+    scroll: usize,
+}
+
+impl Menu {
+    pub fn new(settings: Settings, keymap: Keymap) -> Menu {
+        let mut plays = vec![Play::Mode(Mode::Marathon), Play::Mode(Mode::Finesse)];
+        plays.extend((0..OPENERS.len()).map(|i| Play::Mode(Mode::Opener(i))));
+        plays.extend(puzzle::BUILTIN.iter().map(|(name, _)| Play::Puzzle(name)));
+        Menu {
+            settings,
+            keymap,
+            screen: Screen::Main,
+            plays,
+            play: 0,
+            level: 1,
+            seed: String::new(),
+            item: Item::Start,
+            leaderboard: 0,
+            scores: Ok(Vec::new()),
+            setting: 0,
+            action: 0,
+            message: String::new(),
+            report: Vec::new(),
+            scroll: 0,
+        }
+    }
+
+    /// Shows `lines` about the game just played the next time the menu is run, unless there are none.
+    pub fn report(&mut self, lines: Vec<String>) {
+        if !lines.is_empty() {
+            self.report = lines;
+            self.scroll = 0;
+            self.screen = Screen::Report;
+        }
+    }
+
+    /// Reads the leaderboard picked on the leaderboards screen.
+    fn load_scores(&mut self) {
+        let mode = Mode::SCORED[self.leaderboard].name();
+        self.scores = scores::leaderboard(mode).map_err(|e| e.to_string());
+    }
+
+    /// Shows the menu until the player starts a game or quits.
+    pub fn run(&mut self, display: &mut impl Renderer) -> Choice {
+        loop {
+            display.clear_buffer();
+            self.render(display);
+            display.render();
+
+            if let Some(key) = input::read_key(Some(REDRAW)) {
+                if let Some(choice) = self.keypress(key, display) {
+                    return choice;
+                }
+            }
+        }
+    }
+
+    fn items(&self) -> Vec<Item> {
+        let mut items = vec![Item::Mode, Item::Level, Item::Seed, Item::Start];
+        if snapshot::path().exists() {
+            items.push(Item::Continue);
+        }
+        items.extend([Item::Leaderboards, Item::Settings, Item::Keys, Item::Quit]);
+        items
+    }
+
+    fn nav(&self, key: &Key) -> Option<Nav> {
+        match key {
+            Key::Up => Some(Nav::Up),
+            Key::Down => Some(Nav::Down),
+            Key::Left => Some(Nav::Left),
+            Key::Right => Some(Nav::Right),
+            Key::Enter => Some(Nav::Select),
+            Key::Esc => Some(Nav::Back),
+            _ => match self.keymap.action(key)? {
+                Action::RotateLeft | Action::RotateRight => Some(Nav::Up),
+                Action::SoftDrop => Some(Nav::Down),
+                Action::MoveLeft => Some(Nav::Left),
+                Action::MoveRight => Some(Nav::Right),
+                Action::HardDrop => Some(Nav::Select),
+                Action::Quit => Some(Nav::Back),
+                _ => None,
+            },
+        }
+    }
+
+    fn keypress(&mut self, key: Key, display: &mut impl Renderer) -> Option<Choice> {
+        self.message.clear();
+        match self.screen {
+            Screen::Rebind => {
+                self.rebind(key);
+                return None;
+            }
+            Screen::Main if self.item == Item::Seed => match key {
+                Key::Char(c) if c.is_ascii_digit() => {
+                    if self.seed.len() < SEED_DIGITS {
+                        self.seed.push(c);
+                    }
+                    return None;
+                }
+                Key::Backspace => {
+                    self.seed.pop();
+                    return None;
+                }
+                _ => {}
+            },
+            Screen::Keys if key == Key::Backspace || key == Key::Delete => {
+                self.keymap.unbind(Action::ALL[self.action]);
+                self.save_keymap();
+                return None;
+            }
+            _ => {}
+        }
+
+        let nav = self.nav(&key)?;
+        match self.screen {
+            Screen::Main => return self.choose(nav),
+            Screen::Leaderboards => match nav {
+                Nav::Left | Nav::Right => {
+                    self.leaderboard = cycle(self.leaderboard, Mode::SCORED.len(), step(&nav));
+                    self.load_scores();
+                }
+                Nav::Select | Nav::Back => self.screen = Screen::Main,
+                _ => {}
+            },
+            Screen::Settings => match nav {
+                Nav::Up => self.setting = cycle(self.setting, SETTINGS.len(), -1),
+                Nav::Down => self.setting = cycle(self.setting, SETTINGS.len(), 1),
+                Nav::Left => self.change(-1, display),
+                Nav::Right | Nav::Select => self.change(1, display),
+                Nav::Back => self.screen = Screen::Main,
+            },
+            Screen::Keys => match nav {
+                Nav::Up => self.action = cycle(self.action, Action::ALL.len(), -1),
+                Nav::Down => self.action = cycle(self.action, Action::ALL.len(), 1),
+                Nav::Select => self.screen = Screen::Rebind,
+                Nav::Back => self.screen = Screen::Main,
+                _ => {}
+            },
+            Screen::Rebind => {}
+            Screen::Report => match nav {
+                Nav::Up => self.scroll = self.scroll.saturating_sub(1),
+                Nav::Down => {
+                    let lines = self.report_lines(display.frame().size().0);
+                    self.scroll = (self.scroll + 1).min(lines.len() - 1);
+                }
+                Nav::Select | Nav::Back => self.screen = Screen::Main,
+                _ => {}
+            },
+        }
+        None
+    }
+
+    /// Handles a key on the main screen.
+    fn choose(&mut self, nav: Nav) -> Option<Choice> {
+        let items = self.items();
+        let index = items.iter().position(|&i| i == self.item).unwrap_or(0);
+        match nav {
+            Nav::Up => self.item = items[cycle(index, items.len(), -1)],
+            Nav::Down => self.item = items[cycle(index, items.len(), 1)],
+            Nav::Left | Nav::Right => match self.item {
+                Item::Mode => self.play = cycle(self.play, self.plays.len(), step(&nav)),
+                Item::Level => {
+                    self.level = (self.level as i32 + step(&nav)).clamp(1, MAX_LEVEL as i32) as u32
+                }
+                _ => {}
+            },
+            Nav::Select => match self.item {
+                Item::Mode | Item::Level | Item::Seed | Item::Start => return self.start(),
+                Item::Continue => return self.resume(),
+                Item::Leaderboards => {
+                    self.load_scores();
+                    self.screen = Screen::Leaderboards;
+                }
+                Item::Settings => self.screen = Screen::Settings,
+                Item::Keys => self.screen = Screen::Keys,
+                Item::Quit => return Some(Choice::Quit),
+            },
+            Nav::Back => return Some(Choice::Quit),
+        }
+        None
+    }
+
+    /// Starts a game of the selected mode, level and seed.
+    fn start(&mut self) -> Option<Choice> {
+        let rules = match rules::load(&self.settings.rules) {
+            Ok(rules) => rules,
+            Err(e) => {
+                self.message = e.to_string();
+                return None;
+            }
+        };
+        let seed = self.seed.parse().unwrap_or_else(|_| rand::random());
+        let mut game = match self.plays[self.play] {
+            Play::Mode(mode) => Game::with_seed(mode, seed),
+            Play::Puzzle(name) => match puzzle::load(name) {
+                Ok(puzzle) => Game::with_puzzle(puzzle),
+                Err(e) => {
+                    self.message = e.to_string();
+                    return None;
+                }
+            },
+        };
+        game.set_level(self.level);
+        game.rules = rules;
+        Some(self.prepare(game, false))
+    }
+
+    /// Continues the saved marathon game, which keeps its own rules.
+    fn resume(&mut self) -> Option<Choice> {
+        match snapshot::load(&snapshot::path()) {
+            Ok(snapshot) => Some(self.prepare(Game::from_snapshot(snapshot), true)),
+            Err(e) => {
+                self.message = e.to_string();
+                None
+            }
+        }
+    }
+
+    fn prepare(&self, mut game: Game, resumed: bool) -> Choice {
+        game.hud = self.settings.hud;
+        game.keymap = self.keymap.clone();
+        game.animations = self.settings.animations;
+        Choice::Play {
+            game: Box::new(game),
+            resumed,
+        }
+    }
+
+    /// Changes the selected setting to the next (`step` 1) or previous (-1) value, and saves it.
+    fn change(&mut self, step: i32, display: &mut impl Renderer) {
+        let settings = &mut self.settings;
+        let frame = display.frame_mut();
+        match SETTINGS[self.setting] {
+            Setting::Rules => {
+                let names: Vec<&str> = rules::BUILTIN.iter().map(|(n, _)| *n).collect();
+                settings.rules = next(&names, &settings.rules, step);
+            }
+            Setting::Theme => {
+                let names: Vec<&str> = theme::BUILTIN.iter().map(|(n, _)| *n).collect();
+                settings.theme = next(&names, &settings.theme, step);
+                if let Ok(theme) = theme::load(&settings.theme) {
+                    frame.theme = theme;
+                }
+            }
+            Setting::Style => {
+                settings.style = next(&STYLES, &settings.style, step);
+                frame.cell_style = CellStyle::from_name(&settings.style).unwrap();
+            }
+            Setting::Box => {
+                settings.box_borders = !settings.box_borders;
+                frame.box_borders = settings.box_borders;
+            }
+            Setting::Animations => settings.animations = !settings.animations,
+            Setting::Hud(field) => {
+                settings.hud.toggle(field);
+            }
+        }
+        // Only the changed setting is saved, so that command line options don't become settings
+        let changed = settings.clone();
+        let saved = Settings::update(|saved| match SETTINGS[self.setting] {
+            Setting::Rules => saved.rules = changed.rules,
+            Setting::Theme => saved.theme = changed.theme,
+            Setting::Style => saved.style = changed.style,
+            Setting::Box => saved.box_borders = changed.box_borders,
+            Setting::Animations => saved.animations = changed.animations,
+            Setting::Hud(_) => saved.hud = changed.hud,
+        });
+        if let Err(e) = saved {
+            self.message = format!("Could not save the settings: {}", e);
+        }
+    }
+
+    /// Binds `key` to the action selected on the keys screen, unless it is Esc.
+    fn rebind(&mut self, key: Key) {
+        self.screen = Screen::Keys;
+        if key != Key::Esc {
+            self.keymap.bind(key, Action::ALL[self.action]);
+            self.save_keymap();
+        }
+    }
+
+    fn save_keymap(&mut self) {
+        self.message = match self.keymap.save() {
+            Ok(path) => format!("Saved to {}", path.display()),
+            Err(e) => e.to_string(),
+        };
+    }
+
+    fn render(&self, display: &mut impl Renderer) {
+        let (width, height) = display.frame().size();
+        let bottom = height as u32 - 1;
+        match self.screen {
+            Screen::Main => {
+                let title = "T E T R I S";
+                let x = (width as u32 - title.len() as u32) / 2;
+                display.set_text(title, x, 1, Color::Highlight, Color::Black);
+                for (i, &item) in self.items().iter().enumerate() {
+                    let label = match item {
+                        Item::Mode => format!("{:<14}< {} >", "Mode", self.plays[self.play].name()),
+                        Item::Level => format!("{:<14}< {} >", "Level", self.level),
+                        Item::Seed if !self.seed.is_empty() => {
+                            format!("{:<14}{}", "Seed", self.seed)
+                        }
+                        Item::Seed if self.item == Item::Seed => {
+                            format!("{:<14}random (type a number)", "Seed")
+                        }
+                        Item::Seed => format!("{:<14}random", "Seed"),
+                        Item::Start => "Start".to_string(),
+                        Item::Continue => "Continue saved game".to_string(),
+                        Item::Leaderboards => "Leaderboards".to_string(),
+                        Item::Settings => "Settings".to_string(),
+                        Item::Keys => "Keys".to_string(),
+                        Item::Quit => "Quit".to_string(),
+                    };
+                    draw_line(display, 4 + i as u32, item == self.item, &label);
+                }
+                draw_text(display, &self.message, bottom - 2, Color::Highlight);
+                draw_text(display, HELP, bottom, Color::Text);
+            }
+            Screen::Leaderboards => {
+                let mode = Mode::SCORED[self.leaderboard].name();
+                let title = format!("Leaderboard   < {} >", mode);
+                draw_text(display, &title, 1, Color::Highlight);
+                match &self.scores {
+                    Ok(scores) if scores.is_empty() => {
+                        draw_text(display, "No scores yet", 3, Color::Text)
+                    }
+                    Ok(scores) => {
+                        for (i, score) in scores.iter().enumerate() {
+                            let score = format!("{:>2}. {}", i + 1, score);
+                            draw_text(display, &score, 3 + i as u32, Color::Text);
+                        }
+                    }
+                    Err(e) => draw_text(display, e, 3, Color::Text),
+                }
+                draw_text(display, HELP, bottom, Color::Text);
+            }
+            Screen::Settings => {
+                draw_text(display, "Settings", 1, Color::Highlight);
+                let on_off = |on: bool| if on { "on" } else { "off" };
+                let hud = [
+                    self.settings.hud.timer,
+                    self.settings.hud.pps,
+                    self.settings.hud.apm,
+                    self.settings.hud.kpp,
+                    self.settings.hud.lines,
+                ];
+                for (i, &setting) in SETTINGS.iter().enumerate() {
+                    let (name, value) = match setting {
+                        Setting::Rules => ("Rules", format!("< {} >", self.settings.rules)),
+                        Setting::Theme => ("Theme", format!("< {} >", self.settings.theme)),
+                        Setting::Style => ("Style", format!("< {} >", self.settings.style)),
+                        Setting::Box => ("Box borders", on_off(self.settings.box_borders).into()),
+                        Setting::Animations => {
+                            ("Animations", on_off(self.settings.animations).into())
+                        }
+                        Setting::Hud(field) => {
+                            let index = field as usize - 1;
+                            (HUD_FIELDS[index], on_off(hud[index]).into())
+                        }
+                    };
+                    let label = format!("{:<14}{}", name, value);
+                    draw_line(display, 3 + i as u32, i == self.setting, &label);
+                }
+                draw_text(display, &self.message, bottom - 2, Color::Highlight);
+                draw_text(display, HELP, bottom, Color::Text);
+            }
+            Screen::Keys | Screen::Rebind => {
+                draw_text(display, "Keys", 0, Color::Highlight);
+                for (i, &action) in Action::ALL.iter().enumerate() {
+                    let keys = self.keymap.keys(action).join(", ");
+                    let label = format!("{:<14}{}", action.name(), keys);
+                    draw_line(display, 1 + i as u32, i == self.action, &label);
+                }
+                let status = if let Screen::Rebind = self.screen {
+                    format!(
+                        "Press a key for {} (Esc: cancel)",
+                        Action::ALL[self.action].name()
+                    )
+                } else if self.message.is_empty() {
+                    KEYS_HELP.to_string()
+                } else {
+                    self.message.clone()
+                };
+                draw_text(display, &status, bottom, Color::Highlight);
+            }
+            Screen::Report => {
+                draw_text(display, "Last game", 1, Color::Highlight);
+                let lines = self.report_lines(width);
+                for (i, line) in lines
+                    .iter()
+                    .skip(self.scroll)
+                    .take(bottom as usize - 4)
+                    .enumerate()
+                {
+                    draw_text(display, line, 3 + i as u32, Color::Text);
+                }
+                draw_text(display, REPORT_HELP, bottom, Color::Text);
+            }
+        }
+    }
+
+    /// The report on the last game, with long lines (such as fumen strings) broken up to fit `width`.
+    fn report_lines(&self, width: u16) -> Vec<String> {
+        let width = width as usize - 4;
+        self.report
+            .iter()
+            .flat_map(|line| {
+                let chars: Vec<char> = line.chars().collect();
+                chars
+                    .chunks(width)
+                    .map(|chunk| chunk.iter().collect())
+                    .collect::<Vec<String>>()
+            })
+            .collect()
+    }
+}
+
+fn draw_text(display: &mut impl Renderer, text: &str, y: u32, color: Color) {
+    display.set_text(text, 2, y, color, Color::Black);
+}
+
+/// Draws a line that can be selected, marked and highlighted if it is.
+fn draw_line(display: &mut impl Renderer, y: u32, selected: bool, text: &str) {
+    if selected {
+        draw_text(display, &format!("> {}", text), y, Color::Highlight);
+    } else {
+        draw_text(display, &format!("  {}", text), y, Color::Text);
+    }
+}
+
+/// Moves `index` by `step` through `len` entries, wrapping around at either end.
+fn cycle(index: usize, len: usize, step: i32) -> usize {
+    (index as i32 + step).rem_euclid(len as i32) as usize
+}
+
+fn step(nav: &Nav) -> i32 {
+    match nav {
+        Nav::Left => -1,
+        _ => 1,
+    }
+}
+
+/// The name after (`step` 1) or before (-1) `current` in `names`, or the first name if `current` isn't one
+/// of them.
+fn next(names: &[&str], current: &str, step: i32) -> String {
+    match names.iter().position(|n| *n == current) {
+        Some(i) => names[cycle(i, names.len(), step)].to_string(),
+        None => names[0].to_string(),
+    }
+}
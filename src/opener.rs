@@ -0,0 +1,134 @@
+use crate::board::*;
+use crate::piece::PieceKind::*;
+use crate::piece::*;
+
+/// A known first-bag setup that the opener training mode drills.
+pub struct Opener {
+    pub name: &'static str,
+    /// The order the pieces are dealt in while training. The bag continues randomly afterwards.
+    pub queue: &'static [PieceKind],
+    /// The finished setup, top line first, with the bottom line resting on the floor. Every letter marks
+    /// a cell of the piece with that name; each piece appears once.
+    pub diagram: &'static [&'static str],
+}
+
+#[rustfmt::skip]
+pub const OPENERS: [Opener; 2] = [
+    Opener {
+        name: "TSD",
+        queue: &[J, Z, O, I, L, S, T],
+        diagram: &[
+            "..Z..LS...",
+            ".ZZ..LSS..",
+            "JZTTTLLSOO",
+            "JJJTIIIIOO",
+        ],
+    },
+    Opener {
+        name: "PCO",
+        queue: &[J, S, L, Z, O, I, T],
+        diagram: &[
+            "....SIIIIZ",
+            "....SSOOZZ",
+            "....JSOOZL",
+            "....JJJLLL",
+        ],
+    },
+];
+
+pub fn find(name: &str) -> Option<usize> {
+    OPENERS
+        .iter()
+        .position(|o| o.name.eq_ignore_ascii_case(name))
+}
+
+impl Opener {
+    /// Returns the board cells every piece of the setup ends up on.
+    pub fn placements(&self) -> Vec<(PieceKind, Vec<Point>)> {
+        let top = BOARD_HEIGHT as i32 - self.diagram.len() as i32;
+        let mut placements: Vec<(PieceKind, Vec<Point>)> = Vec::new();
+        for (row, line) in self.diagram.iter().enumerate() {
+            for (col, c) in line.chars().enumerate() {
+                let kind = match PieceKind::ALL.iter().find(|k| k.name().starts_with(c)) {
+                    Some(&k) => k,
+                    None => continue,
+                };
+                let cell = Point {
+                    x: col as i32,
+                    y: top + row as i32,
+                };
+                match placements.iter_mut().find(|p| p.0 == kind) {
+                    Some(p) => p.1.push(cell),
+                    None => placements.push((kind, vec![cell])),
+                }
+            }
+        }
+        placements
+    }
+}
+
+/// Checks the player's placements against an opener while it is being built.
+#[derive(Clone)]
+pub struct OpenerTrainer {
+    pub opener: &'static Opener,
+    targets: Vec<(PieceKind, Vec<Point>)>,
+    placed: Vec<bool>,
+    pub attempts: u32,
+    pub completed: u32,
+}
+
+impl OpenerTrainer {
+    pub fn new(opener: &'static Opener) -> OpenerTrainer {
+        let targets = opener.placements();
+        OpenerTrainer {
+            opener,
+            placed: vec![false; targets.len()],
+            targets,
+            attempts: 1,
+            completed: 0,
+        }
+    }
+
+    /// True once every piece of the setup has been placed.
+    pub fn finished(&self) -> bool {
+        self.placed.iter().all(|&p| p)
+    }
+
+    /// Starts another attempt at the opener.
+    pub fn restart(&mut self) {
+        self.placed.iter_mut().for_each(|p| *p = false);
+        self.attempts += 1;
+    }
+
+    /// Checks a piece that is about to lock at `position`. Returns false if it doesn't match any of the
+    /// setup's remaining placements. Once the setup is finished every placement is accepted.
+    pub fn check(&mut self, piece: &Piece, position: Point) -> bool {
+        if self.finished() {
+            return true;
+        }
+
+        let mut cells = piece.cells(position);
+        cells.sort_by_key(|p| (p.y, p.x));
+        for (i, (kind, target)) in self.targets.iter().enumerate() {
+            let mut target = target.clone();
+            target.sort_by_key(|p| (p.y, p.x));
+            if !self.placed[i] && *kind == piece.kind && target == cells {
+                self.placed[i] = true;
+                if self.finished() {
+                    self.completed += 1;
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Returns the placements that still have to be made.
+    pub fn remaining(&self) -> impl Iterator<Item = &(PieceKind, Vec<Point>)> {
+        self.targets
+            .iter()
+            .zip(self.placed.iter())
+            .filter(|(_, &placed)| !placed)
+            .map(|(t, _)| t)
+    }
+}
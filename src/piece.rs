@@ -1,6 +1,46 @@
+use crate::board::Point;
 use crate::util::*;
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
+pub enum PieceKind {
+    I,
+    O,
+    T,
+    S,
+    Z,
+    J,
+    L,
+}
+
+impl PieceKind {
+    pub const ALL: [PieceKind; 7] = [
+        PieceKind::I,
+        PieceKind::O,
+        PieceKind::T,
+        PieceKind::S,
+        PieceKind::Z,
+        PieceKind::J,
+        PieceKind::L,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PieceKind::I => "I",
+            PieceKind::O => "O",
+            PieceKind::T => "T",
+            PieceKind::S => "S",
+            PieceKind::Z => "Z",
+            PieceKind::J => "J",
+            PieceKind::L => "L",
+        }
+    }
+}
 
 pub struct Piece {
+    pub kind: PieceKind,
     pub color: Color,
     pub shape: Vec<Vec<u8>>,
 }
@@ -8,6 +48,7 @@ pub struct Piece {
 impl Clone for Piece {
     fn clone(&self) -> Piece {
         let mut p = Piece {
+            kind: self.kind,
             color: self.color,
             shape: Vec::with_capacity(self.shape.len()),
         };
@@ -19,8 +60,21 @@ impl Clone for Piece {
 }
 
 impl Piece {
+    pub fn new(kind: PieceKind) -> Piece {
+        match kind {
+            PieceKind::I => Piece::new_i(),
+            PieceKind::O => Piece::new_o(),
+            PieceKind::T => Piece::new_t(),
+            PieceKind::S => Piece::new_s(),
+            PieceKind::Z => Piece::new_z(),
+            PieceKind::J => Piece::new_j(),
+            PieceKind::L => Piece::new_l(),
+        }
+    }
+
     pub fn new_o() -> Piece {
         Piece {
+            kind: PieceKind::O,
             color: Color::Yellow,
             shape: vec![vec![1, 1], vec![1, 1]],
         }
@@ -28,6 +82,7 @@ impl Piece {
 
     pub fn new_l() -> Piece {
         Piece {
+            kind: PieceKind::L,
             color: Color::Orange,
             shape: vec![vec![0, 0, 1], vec![1, 1, 1], vec![0, 0, 0]],
         }
@@ -35,6 +90,7 @@ impl Piece {
 
     pub fn new_j() -> Piece {
         Piece {
+            kind: PieceKind::J,
             color: Color::Blue,
             shape: vec![vec![1, 0, 0], vec![1, 1, 1], vec![0, 0, 0]],
         }
@@ -42,6 +98,7 @@ impl Piece {
 
     pub fn new_t() -> Piece {
         Piece {
+            kind: PieceKind::T,
             color: Color::Purple,
             shape: vec![vec![0, 1, 0], vec![1, 1, 1], vec![0, 0, 0]],
         }
@@ -49,6 +106,7 @@ impl Piece {
 
     pub fn new_s() -> Piece {
         Piece {
+            kind: PieceKind::S,
             color: Color::Green,
             shape: vec![vec![0, 1, 1], vec![1, 1, 0], vec![0, 0, 0]],
         }
@@ -56,6 +114,7 @@ impl Piece {
 
     pub fn new_z() -> Piece {
         Piece {
+            kind: PieceKind::Z,
             color: Color::Red,
             shape: vec![vec![1, 1, 0], vec![0, 1, 1], vec![0, 0, 0]],
         }
@@ -63,6 +122,7 @@ impl Piece {
 
     pub fn new_i() -> Piece {
         Piece {
+            kind: PieceKind::I,
             color: Color::Cyan,
             shape: vec![
                 vec![0, 0, 0, 0],
@@ -74,6 +134,11 @@ impl Piece {
     }
 
     pub fn rotate(&mut self, direction: Direction) {
+        if direction == Direction::Half {
+            self.rotate(Direction::Right);
+            self.rotate(Direction::Right);
+            return;
+        }
         let size = self.shape.len();
 
         for row in 0..size / 2 {
@@ -97,11 +162,24 @@ impl Piece {
                             self.shape[col][size - row - 1];
                         self.shape[col][size - row - 1] = t;
                     }
+                    Direction::Half => unreachable!(),
                 }
             }
         }
     }
 
+    /// Returns the board cells the piece covers when placed at `origin`.
+    pub fn cells(&self, origin: Point) -> Vec<Point> {
+        let mut cells = Vec::with_capacity(4);
+        self.each_point(&mut |row, col| {
+            cells.push(Point {
+                x: origin.x + col,
+                y: origin.y + row,
+            })
+        });
+        cells
+    }
+
     pub fn each_point(&self, callback: &mut dyn FnMut(i32, i32)) {
         let piece_width = self.shape.len() as i32;
         for row in 0..piece_width {
@@ -114,51 +192,119 @@ impl Piece {
     }
 
     pub fn get_shadow_color(&self) -> Color {
-        match self.color {
-            Color::Cyan => Color::DarkCyan,
-            Color::Purple => Color::DarkPurple,
-            Color::Green => Color::DarkGreen,
-            Color::Red => Color::DarkRed,
-            Color::Blue => Color::DarkBlue,
-            Color::Orange => Color::DarkOrange,
-            Color::Yellow => Color::DarkYellow,
-            _ => Color::Black,
-        }
+        self.color.shadow()
     }
 }
 
+/// The queued pieces and random number generator of a `PieceBag`, for saving a game.
+#[derive(Serialize, Deserialize)]
+pub struct BagState {
+    pieces: Vec<PieceKind>,
+    rng: Pcg32,
+    #[serde(default)]
+    finite: bool,
+    #[serde(default)]
+    given: usize,
+}
+
 /// Implements a queue of randomized tetrominoes.
 ///
 /// Instead of a purely random stream of tetromino types, this queue generates a random ordering of all
 /// possible types and ensures all of those pieces are used before re-generating a new random set. This helps
 /// avoid pathological cases where purely random generation provides the same piece type repeately in a row,
 /// or fails to provide a required piece for a very long time.
+#[derive(Clone)]
 pub struct PieceBag {
     pieces: Vec<Piece>,
+    rng: Pcg32,
+    /// Whether the bag ends with its queue instead of generating more pieces.
+    finite: bool,
+    /// How many of the queued pieces, from the front, were given in a fixed order rather than shuffled.
+    given: usize,
 }
 
 impl PieceBag {
-    pub fn new() -> PieceBag {
-        let mut p = PieceBag { pieces: Vec::new() };
+    /// How the bag generates pieces, as recorded with scores.
+    pub const RANDOMIZER: &'static str = "7-bag";
+
+    /// Creates a bag whose sequence of pieces is fully determined by `seed`.
+    pub fn with_seed(seed: u64) -> PieceBag {
+        let mut p = PieceBag {
+            pieces: Vec::new(),
+            rng: Pcg32::seed_from_u64(seed),
+            finite: false,
+            given: 0,
+        };
         p.fill_bag();
         p
     }
 
-    /// Removes and returns the next piece in the queue.
+    /// Creates a bag that hands out `queue` first and continues with bags generated from `seed` once the
+    /// queue runs out.
+    pub fn with_queue(seed: u64, queue: &[PieceKind]) -> PieceBag {
+        if queue.is_empty() {
+            return PieceBag::with_seed(seed);
+        }
+        PieceBag {
+            pieces: queue.iter().map(|&k| Piece::new(k)).collect(),
+            rng: Pcg32::seed_from_u64(seed),
+            finite: false,
+            given: queue.len(),
+        }
+    }
+
+    /// Creates a bag that hands out `queue` and nothing after it, as a puzzle does.
+    pub fn finite(queue: &[PieceKind]) -> PieceBag {
+        PieceBag {
+            pieces: queue.iter().map(|&k| Piece::new(k)).collect(),
+            rng: Pcg32::seed_from_u64(0),
+            finite: true,
+            given: queue.len(),
+        }
+    }
+
+    /// Recreates a bag saved with `state`, which continues with exactly the same pieces.
+    pub fn from_state(state: BagState) -> PieceBag {
+        PieceBag {
+            pieces: state.pieces.into_iter().map(Piece::new).collect(),
+            rng: state.rng,
+            finite: state.finite,
+            given: state.given,
+        }
+    }
+
+    pub fn state(&self) -> BagState {
+        BagState {
+            pieces: self.pieces.iter().map(|p| p.kind).collect(),
+            rng: self.rng.clone(),
+            finite: self.finite,
+            given: self.given,
+        }
+    }
+
+    /// Removes and returns the next piece in the queue. Panics if a finite bag has run out.
     pub fn pop(&mut self) -> Piece {
         let piece = self.pieces.remove(0);
-        if self.pieces.is_empty() {
+        self.given = self.given.saturating_sub(1);
+        if self.pieces.is_empty() && !self.finite {
             self.fill_bag();
         }
         piece
     }
 
-    /// Returns a copy of the next piece in the queue.
-    pub fn peek(&self) -> Piece {
-        match self.pieces.first() {
-            Some(p) => p.clone(),
-            None => panic!("No next piece in piece bag"),
-        }
+    /// Returns a copy of the next piece in the queue, or None once a finite bag has run out.
+    pub fn peek(&self) -> Option<Piece> {
+        self.pieces.first().cloned()
+    }
+
+    /// Returns the pieces queued after the next one. These are the unseen rest of the current bag.
+    pub fn remaining(&self) -> Vec<PieceKind> {
+        self.pieces.iter().skip(1).map(|p| p.kind).collect()
+    }
+
+    /// Whether every queued piece comes in a known order, as they were given rather than shuffled.
+    pub fn known_order(&self) -> bool {
+        self.given >= self.pieces.len()
     }
 
     /// Generates a random ordering of all possible pieces and adds them to the piece queue.
@@ -175,9 +321,8 @@ impl PieceBag {
             Piece::new_i(),
         ];
 
-        let mut rng = rand::thread_rng();
         while !pieces.is_empty() {
-            let i = rng.gen::<usize>() % pieces.len();
+            let i = self.rng.gen::<usize>() % pieces.len();
             self.pieces.push(pieces.swap_remove(i));
         }
     }
@@ -0,0 +1,401 @@
+use crate::board::*;
+use crate::fumen;
+use crate::piece::*;
+use crate::util::Color;
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+
+/// What has to be achieved before the puzzle's pieces run out.
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Goal {
+    /// Clear at least this many lines in total.
+    Lines(u32),
+    /// Perform a T-spin that clears at least this many lines.
+    TSpin(u32),
+    PerfectClear,
+}
+
+impl Goal {
+    pub fn describe(self) -> String {
+        match self {
+            Goal::Lines(n) => format!("clear {} lines", n),
+            Goal::TSpin(1) => "T-spin single".to_string(),
+            Goal::TSpin(2) => "T-spin double".to_string(),
+            Goal::TSpin(3) => "T-spin triple".to_string(),
+            Goal::TSpin(_) => "T-spin".to_string(),
+            Goal::PerfectClear => "perfect clear".to_string(),
+        }
+    }
+}
+
+/// A puzzle as written in a puzzle file:
+///
+/// ```toml
+/// name = "Hold the I"
+/// goal = "perfect-clear"   # or goal = { lines = 4 } / goal = { t-spin = 2 }
+/// queue = "IZS"
+/// hold = "O"               # optional
+/// board = """
+/// XXX....XXX
+/// XX..XX..XX
+/// """
+/// ```
+///
+/// The board lists the bottom lines of the playfield, top line first. `.` or a space is an empty cell, a
+/// piece letter is a cell of that piece's colour and any other character is a grey garbage cell. Instead of
+/// `board`, the first page of a fumen can be given with `fumen = "v115@..."`.
+#[derive(Deserialize)]
+struct PuzzleFile {
+    name: String,
+    goal: Goal,
+    queue: String,
+    hold: Option<String>,
+    #[serde(default)]
+    board: String,
+    /// A fumen whose first page is used as the board instead of `board`.
+    fumen: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Puzzle {
+    pub name: String,
+    pub goal: Goal,
+    pub queue: Vec<PieceKind>,
+    pub hold: Option<PieceKind>,
+    pub board: Board,
+}
+
+#[derive(Debug)]
+pub enum PuzzleError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for PuzzleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PuzzleError::Io(e) => write!(f, "could not read puzzle: {}", e),
+            PuzzleError::Parse(e) => write!(f, "could not parse puzzle: {}", e),
+            PuzzleError::Invalid(e) => write!(f, "invalid puzzle: {}", e),
+        }
+    }
+}
+
+/// Puzzles that ship with the game, available by name.
+pub const BUILTIN: [(&str, &str); 3] = [
+    ("tetris", include_str!("../puzzles/tetris.toml")),
+    ("tsd", include_str!("../puzzles/tsd.toml")),
+    ("pc", include_str!("../puzzles/pc.toml")),
+];
+
+/// Loads a puzzle from a file, or from the built-in pack if no such file exists.
+pub fn load(name: &str) -> Result<Puzzle, PuzzleError> {
+    if let Some((_, text)) = BUILTIN.iter().find(|(n, _)| *n == name) {
+        if !Path::new(name).exists() {
+            return parse(text);
+        }
+    }
+    let text = std::fs::read_to_string(name).map_err(PuzzleError::Io)?;
+    parse(&text)
+}
+
+pub fn parse(text: &str) -> Result<Puzzle, PuzzleError> {
+    let file: PuzzleFile = toml::from_str(text).map_err(PuzzleError::Parse)?;
+
+    let queue = parse_pieces(&file.queue)?;
+    if queue.is_empty() {
+        return Err(PuzzleError::Invalid("the queue is empty".to_string()));
+    }
+    let hold = match &file.hold {
+        Some(h) => match parse_pieces(h)?.as_slice() {
+            [kind] => Some(*kind),
+            _ => {
+                return Err(PuzzleError::Invalid(
+                    "hold must be a single piece".to_string(),
+                ))
+            }
+        },
+        None => None,
+    };
+
+    Ok(Puzzle {
+        name: file.name,
+        goal: file.goal,
+        queue,
+        hold,
+        board: match &file.fumen {
+            Some(f) => parse_fumen(f)?,
+            None => parse_board(&file.board)?,
+        },
+    })
+}
+
+/// Writes `puzzle` in the puzzle file format.
+pub fn to_toml(puzzle: &Puzzle) -> String {
+    let pieces = |kinds: &[PieceKind]| kinds.iter().map(|k| k.name()).collect::<String>();
+    let mut text = format!("name = {}\n", toml::Value::String(puzzle.name.clone()));
+    text += &match puzzle.goal {
+        Goal::Lines(n) => format!("goal = {{ lines = {} }}\n", n),
+        Goal::TSpin(n) => format!("goal = {{ t-spin = {} }}\n", n),
+        Goal::PerfectClear => "goal = \"perfect-clear\"\n".to_string(),
+    };
+    text += &format!("queue = \"{}\"\n", pieces(&puzzle.queue));
+    if let Some(hold) = puzzle.hold {
+        text += &format!("hold = \"{}\"\n", hold.name());
+    }
+
+    text += "board = \"\"\"\n";
+    let top = puzzle
+        .board
+        .cells
+        .iter()
+        .position(|row| row.iter().any(|c| c.is_some()))
+        .unwrap_or(BOARD_HEIGHT as usize);
+    for row in &puzzle.board.cells[top..] {
+        for cell in row {
+            text.push(match cell {
+                None => '.',
+                Some(color) => PieceKind::ALL
+                    .iter()
+                    .find(|k| Piece::new(**k).color == *color)
+                    .map_or('X', |k| k.name().chars().next().unwrap()),
+            });
+        }
+        text.push('\n');
+    }
+    text += "\"\"\"\n";
+    text
+}
+
+fn parse_fumen(text: &str) -> Result<Board, PuzzleError> {
+    let pages = fumen::decode(text).map_err(|e| PuzzleError::Invalid(e.to_string()))?;
+    match pages.into_iter().next() {
+        Some(page) => Ok(page.board),
+        None => Err(PuzzleError::Invalid("the fumen has no pages".to_string())),
+    }
+}
+
+fn parse_pieces(text: &str) -> Result<Vec<PieceKind>, PuzzleError> {
+    text.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| {
+            piece_kind(c).ok_or_else(|| PuzzleError::Invalid(format!("unknown piece '{}'", c)))
+        })
+        .collect()
+}
+
+fn piece_kind(c: char) -> Option<PieceKind> {
+    PieceKind::ALL
+        .iter()
+        .find(|k| k.name().starts_with(c.to_ascii_uppercase()))
+        .copied()
+}
+
+fn parse_board(text: &str) -> Result<Board, PuzzleError> {
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    if lines.len() > BOARD_HEIGHT as usize {
+        return Err(PuzzleError::Invalid(format!(
+            "the board has {} lines, at most {} fit",
+            lines.len(),
+            BOARD_HEIGHT
+        )));
+    }
+
+    let mut board = Board::new();
+    let top = BOARD_HEIGHT as usize - lines.len();
+    for (row, line) in lines.iter().enumerate() {
+        if line.chars().count() > BOARD_WIDTH as usize {
+            return Err(PuzzleError::Invalid(format!(
+                "board line \"{}\" is wider than {} cells",
+                line, BOARD_WIDTH
+            )));
+        }
+        for (col, c) in line.chars().enumerate() {
+            board.cells[top + row][col] = match c {
+                '.' | ' ' => None,
+                c => Some(piece_kind(c).map_or(Color::Gray, |k| Piece::new(k).color)),
+            };
+        }
+    }
+    Ok(board)
+}
+
+/// Tracks an attempt at a puzzle.
+#[derive(Clone)]
+pub struct PuzzleState {
+    pub puzzle: Puzzle,
+    /// Pieces from the queue and hold that have not been locked yet. The held piece comes in once the queue
+    /// runs out, so every one of them can be played.
+    pub pieces_left: usize,
+    lines: u32,
+    /// Some(true) once the goal is reached, Some(false) once the pieces run out without reaching it.
+    pub solved: Option<bool>,
+}
+
+impl PuzzleState {
+    pub fn new(puzzle: Puzzle) -> PuzzleState {
+        let mut state = PuzzleState {
+            puzzle,
+            pieces_left: 0,
+            lines: 0,
+            solved: None,
+        };
+        state.restart();
+        state
+    }
+
+    pub fn restart(&mut self) {
+        self.pieces_left = self.puzzle.queue.len() + self.puzzle.hold.map_or(0, |_| 1);
+        self.lines = 0;
+        self.solved = None;
+    }
+
+    /// Records a locked piece that cleared `cleared` lines and decides whether the puzzle is over.
+    pub fn locked(&mut self, cleared: u32, tspin: bool, board: &Board) {
+        self.pieces_left = self.pieces_left.saturating_sub(1);
+        self.lines += cleared;
+
+        let reached = match self.puzzle.goal {
+            Goal::Lines(n) => self.lines >= n,
+            Goal::TSpin(n) => tspin && cleared >= n,
+            Goal::PerfectClear => cleared > 0 && board.is_empty(),
+        };
+        if reached {
+            self.solved = Some(true);
+        } else if self.pieces_left == 0 {
+            self.solved = Some(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUZZLE: &str = r#"
+name = "Test"
+goal = { lines = 2 }
+queue = "I z"
+hold = "O"
+board = """
+T.........
+XXXX.XXXXX
+"""
+"#;
+
+    fn state(goal: Goal) -> PuzzleState {
+        let mut puzzle = parse(PUZZLE).unwrap();
+        puzzle.goal = goal;
+        PuzzleState::new(puzzle)
+    }
+
+    fn invalid(text: &str) -> bool {
+        matches!(parse(text), Err(PuzzleError::Invalid(_)))
+    }
+
+    #[test]
+    fn parses_a_puzzle() {
+        let puzzle = parse(PUZZLE).unwrap();
+        assert_eq!(puzzle.name, "Test");
+        assert_eq!(puzzle.goal, Goal::Lines(2));
+        assert_eq!(puzzle.queue, vec![PieceKind::I, PieceKind::Z]);
+        assert_eq!(puzzle.hold, Some(PieceKind::O));
+        let bottom = BOARD_HEIGHT as usize - 1;
+        assert_eq!(puzzle.board.cells[bottom - 1][0], Some(Color::Purple));
+        assert_eq!(puzzle.board.cells[bottom - 1][1], None);
+        assert_eq!(puzzle.board.cells[bottom][0], Some(Color::Gray));
+        assert_eq!(puzzle.board.cells[bottom][4], None);
+        assert!(puzzle.board.cells[..bottom - 1]
+            .iter()
+            .all(|row| row.iter().all(|c| c.is_none())));
+    }
+
+    #[test]
+    fn builtin_puzzles_parse() {
+        for (name, _) in BUILTIN.iter() {
+            assert!(load(name).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn saved_puzzle_reads_back() {
+        let puzzle = parse(PUZZLE).unwrap();
+        let saved = parse(&to_toml(&puzzle)).unwrap();
+        assert_eq!(saved.name, puzzle.name);
+        assert_eq!(saved.goal, puzzle.goal);
+        assert_eq!(saved.queue, puzzle.queue);
+        assert_eq!(saved.hold, puzzle.hold);
+        assert_eq!(saved.board.cells, puzzle.board.cells);
+    }
+
+    #[test]
+    fn invalid_puzzles() {
+        let puzzle = |queue: &str, hold: &str, board: &str| {
+            format!(
+                "name = \"x\"\ngoal = \"perfect-clear\"\nqueue = \"{}\"\n{}board = \"{}\"\n",
+                queue, hold, board
+            )
+        };
+        assert!(!invalid(&puzzle("T", "", "X")));
+        assert!(invalid(&puzzle("", "", "X")));
+        assert!(invalid(&puzzle("TW", "", "X")));
+        assert!(invalid(&puzzle("T", "hold = \"IO\"\n", "X")));
+        assert!(invalid(&puzzle("T", "", "XXXXXXXXXXX")));
+        assert!(invalid(&puzzle("T", "", &"X\\n".repeat(21))));
+        assert!(matches!(
+            parse("name = \"x\"\ngoal = { spins = 1 }\nqueue = \"T\""),
+            Err(PuzzleError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn lines_add_up() {
+        let mut state = state(Goal::Lines(2));
+        state.locked(1, false, &Board::new());
+        assert_eq!(state.solved, None);
+        state.locked(1, false, &Board::new());
+        assert_eq!(state.solved, Some(true));
+    }
+
+    #[test]
+    fn t_spin_needs_the_spin_and_the_lines() {
+        let mut state = state(Goal::TSpin(2));
+        state.locked(2, false, &Board::new());
+        state.locked(1, true, &Board::new());
+        assert_eq!(state.solved, None);
+        state.locked(2, true, &Board::new());
+        assert_eq!(state.solved, Some(true));
+    }
+
+    #[test]
+    fn perfect_clear_needs_an_empty_board() {
+        let mut state = state(Goal::PerfectClear);
+        let mut board = Board::new();
+        board.cells[0][0] = Some(Color::Gray);
+        state.locked(1, false, &board);
+        assert_eq!(state.solved, None);
+        // An empty board only counts if the piece cleared it
+        state.locked(0, false, &Board::new());
+        assert_eq!(state.solved, None);
+        state.locked(1, false, &Board::new());
+        assert_eq!(state.solved, Some(true));
+    }
+
+    #[test]
+    fn fails_once_queue_and_hold_are_used() {
+        let mut state = state(Goal::Lines(4));
+        assert_eq!(state.pieces_left, 3);
+        for _ in 0..2 {
+            state.locked(1, false, &Board::new());
+            assert_eq!(state.solved, None);
+        }
+        state.locked(1, false, &Board::new());
+        assert_eq!(state.solved, Some(false));
+
+        state.restart();
+        assert_eq!((state.pieces_left, state.solved), (3, None));
+    }
+}
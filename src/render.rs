@@ -0,0 +1,297 @@
+use crate::theme::Theme;
+use crate::util::Color;
+
+/// Rewriting a few unchanged cells is cheaper than a cursor move to skip over them.
+const MAX_SKIP_REWRITE: usize = 6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pixel {
+    pub c: char,
+    pub fg_color: Color,
+    pub bg_color: Color,
+}
+
+const BLANK: Pixel = Pixel {
+    c: ' ',
+    fg_color: Color::Black,
+    bg_color: Color::Black,
+};
+
+/// How board cells are drawn.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CellStyle {
+    /// Two spaces on the cell's colour.
+    Blocks,
+    /// Two full block characters in the cell's colour.
+    Solid,
+    /// `[]` in the cell's colour.
+    Brackets,
+    /// Half blocks, so that two rows of the playfield fit on one line of the terminal.
+    Small,
+}
+
+impl CellStyle {
+    pub fn from_name(name: &str) -> Option<CellStyle> {
+        match name {
+            "blocks" => Some(CellStyle::Blocks),
+            "solid" => Some(CellStyle::Solid),
+            "brackets" => Some(CellStyle::Brackets),
+            "small" => Some(CellStyle::Small),
+            _ => None,
+        }
+    }
+}
+
+/// The characters and colours of one frame, and how cells are to be drawn in it.
+pub struct Frame {
+    rows: Vec<Vec<Pixel>>,
+    pub theme: Theme,
+    pub cell_style: CellStyle,
+    /// Whether borders use box-drawing characters rather than ASCII.
+    pub box_borders: bool,
+}
+
+impl Frame {
+    pub fn new(width: u32, height: u32) -> Frame {
+        Frame {
+            rows: vec![vec![BLANK; width as usize]; height as usize],
+            theme: Theme::default(),
+            cell_style: CellStyle::Blocks,
+            box_borders: false,
+        }
+    }
+
+    /// The frame's width and height, which is the smallest terminal it fits on.
+    pub fn size(&self) -> (u16, u16) {
+        let width = self.rows.first().map_or(0, |row| row.len());
+        (width as u16, self.rows.len() as u16)
+    }
+
+    pub fn rows(&self) -> &[Vec<Pixel>] {
+        &self.rows
+    }
+
+    pub fn set_text(&mut self, text: &str, x: u32, y: u32, fg_color: Color, bg_color: Color) {
+        // Text running off the frame is cut off.
+        let row = match self.rows.get_mut(y as usize) {
+            Some(row) => row,
+            None => return,
+        };
+        let cells = row.iter_mut().skip(x as usize);
+        for (cell, c) in cells.zip(text.chars()) {
+            cell.c = c;
+            cell.fg_color = fg_color;
+            cell.bg_color = bg_color;
+        }
+    }
+
+    /// Draws a cell of `color`, two characters wide: the theme's glyph for it if it has one, and otherwise
+    /// in the cell style. Small cells are drawn as blocks here; see `set_half_cell`.
+    pub fn set_cell(&mut self, x: u32, y: u32, color: Color) {
+        if let Some(glyph) = self.theme.glyph(color).map(str::to_string) {
+            self.set_text(&glyph, x, y, color, Color::Black);
+            return;
+        }
+        match self.cell_style {
+            CellStyle::Blocks | CellStyle::Small => self.set_text("  ", x, y, color, color),
+            CellStyle::Solid => self.set_text("██", x, y, color, Color::Black),
+            CellStyle::Brackets => self.set_text("[]", x, y, color, Color::Black),
+        }
+    }
+
+    /// Draws the upper or lower half of a two characters wide cell of `color`, keeping the colour of the
+    /// other half if it was drawn before.
+    pub fn set_half_cell(&mut self, x: u32, y: u32, lower: bool, color: Color) {
+        let row = match self.rows.get_mut(y as usize) {
+            Some(row) => row,
+            None => return,
+        };
+        // An upper half block shows its foreground colour above its background colour.
+        for cell in row.iter_mut().skip(x as usize).take(2) {
+            if cell.c != '▀' {
+                *cell = Pixel { c: '▀', ..BLANK };
+            }
+            if lower {
+                cell.bg_color = color;
+            } else {
+                cell.fg_color = color;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for row in self.rows.iter_mut() {
+            for cell in row.iter_mut() {
+                *cell = BLANK;
+            }
+        }
+    }
+
+    /// Returns the runs of cells that differ from `previous`, or of every cell if there is no previous
+    /// frame. Short stretches of unchanged cells between changes are included in the runs.
+    fn changes(&self, previous: Option<&[Vec<Pixel>]>) -> Vec<Run<'_>> {
+        let mut runs: Vec<Run> = Vec::new();
+        for (y, row) in self.rows.iter().enumerate() {
+            let changed = (0..row.len()).filter(|&x| previous.is_none_or(|p| p[y][x] != row[x]));
+            let mut current: Option<(usize, usize)> = None;
+            for x in changed {
+                current = match current {
+                    Some((start, end)) if x - end <= MAX_SKIP_REWRITE => Some((start, x + 1)),
+                    Some((start, end)) => {
+                        runs.push(Run {
+                            x: start,
+                            y,
+                            pixels: &row[start..end],
+                        });
+                        Some((x, x + 1))
+                    }
+                    None => Some((x, x + 1)),
+                };
+            }
+            if let Some((start, end)) = current {
+                runs.push(Run {
+                    x: start,
+                    y,
+                    pixels: &row[start..end],
+                });
+            }
+        }
+        runs
+    }
+}
+
+/// Something the game can be drawn on. Drawing goes into the renderer's frame, which is shown by `render`.
+pub trait Renderer {
+    fn frame(&self) -> &Frame;
+
+    fn frame_mut(&mut self) -> &mut Frame;
+
+    /// Shows what was drawn since the last call.
+    fn render(&mut self);
+
+    /// Whether the whole frame can be seen.
+    fn fits(&self) -> bool {
+        true
+    }
+
+    fn set_text<S: AsRef<str>>(
+        &mut self,
+        text: S,
+        x: u32,
+        y: u32,
+        fg_color: Color,
+        bg_color: Color,
+    ) {
+        self.frame_mut()
+            .set_text(text.as_ref(), x, y, fg_color, bg_color);
+    }
+
+    fn set_cell(&mut self, x: u32, y: u32, color: Color) {
+        self.frame_mut().set_cell(x, y, color);
+    }
+
+    fn set_half_cell(&mut self, x: u32, y: u32, lower: bool, color: Color) {
+        self.frame_mut().set_half_cell(x, y, lower, color);
+    }
+
+    fn clear_buffer(&mut self) {
+        self.frame_mut().clear();
+    }
+
+    /// The style cells are drawn in. Half blocks only show colours, so a theme that tells cells apart by
+    /// their glyphs is drawn in blocks rather than in the small style.
+    fn cell_style(&self) -> CellStyle {
+        let frame = self.frame();
+        match frame.cell_style {
+            CellStyle::Small if frame.theme.has_glyphs() => CellStyle::Blocks,
+            style => style,
+        }
+    }
+
+    fn box_borders(&self) -> bool {
+        self.frame().box_borders
+    }
+}
+
+/// A stretch of cells of row `y` to write, starting at column `x`.
+pub struct Run<'a> {
+    pub x: usize,
+    pub y: usize,
+    pub pixels: &'a [Pixel],
+}
+
+/// What a terminal renderer has to write to show the next frame.
+pub enum Update<'a> {
+    /// The terminal is too small for the frame, so a notice with these lines is shown instead. The lines
+    /// are empty if the notice is already shown.
+    TooSmall(Vec<String>),
+    /// The frame's offset on the terminal and the cells that changed. `clear` if the terminal has to be
+    /// cleared first.
+    Draw {
+        clear: bool,
+        left: usize,
+        top: usize,
+        runs: Vec<Run<'a>>,
+    },
+}
+
+/// The notice shown instead of `frame` on a terminal of `size` that is too small for it, cut off at the
+/// terminal's width.
+pub fn too_small(frame: &Frame, size: (u16, u16)) -> Vec<String> {
+    let (width, height) = frame.size();
+    let lines = [
+        "Terminal too small".to_string(),
+        format!("Need {}x{}, have {}x{}", width, height, size.0, size.1),
+    ];
+    lines
+        .iter()
+        .map(|l| l.chars().take(size.0 as usize).collect())
+        .collect()
+}
+
+/// What was last shown on the terminal.
+struct Shown {
+    /// The terminal size at the time.
+    size: (u16, u16),
+    /// The frame drawn, or None if the terminal was too small and the notice was shown instead.
+    rows: Option<Vec<Vec<Pixel>>>,
+}
+
+/// Remembers what a terminal shows, so that terminal renderers only have to write what changed. The
+/// frame is centred on the terminal, and everything is drawn again when the terminal is resized.
+#[derive(Default)]
+pub struct Screen {
+    previous: Option<Shown>,
+}
+
+impl Screen {
+    /// Works out how to show `frame` on a terminal of `size`, and remembers it as shown.
+    pub fn update<'a>(&mut self, frame: &'a Frame, size: (u16, u16)) -> Update<'a> {
+        let (width, height) = frame.size();
+        let previous = match self.previous.take() {
+            Some(shown) if shown.size == size => Some(shown.rows),
+            _ => None,
+        };
+
+        if size.0 < width || size.1 < height {
+            self.previous = Some(Shown { size, rows: None });
+            if previous.is_some() {
+                return Update::TooSmall(Vec::new());
+            }
+            return Update::TooSmall(too_small(frame, size));
+        }
+
+        let previous = previous.flatten();
+        let runs = frame.changes(previous.as_deref());
+        self.previous = Some(Shown {
+            size,
+            rows: Some(frame.rows.clone()),
+        });
+        Update::Draw {
+            clear: previous.is_none(),
+            left: (size.0 - width) as usize / 2,
+            top: (size.1 - height) as usize / 2,
+            runs,
+        }
+    }
+}
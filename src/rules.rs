@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+/// A rule set as written in a rule set file:
+///
+/// ```toml
+/// name = "modern"        # defaults to the file name
+/// rotate_180 = true      # whether pieces can be turned around in one go
+/// entry_delay = 100      # milliseconds between a piece locking and the next one coming in
+/// line_clear_delay = 300 # further milliseconds when the piece cleared lines
+/// soft_drop_points = 1   # points for every row a piece is soft dropped
+/// hard_drop_points = 2   # points for every row a piece is hard dropped
+/// ```
+///
+/// Rules that aren't given are the classic ones.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    name: Option<String>,
+    rotate_180: Option<bool>,
+    entry_delay: Option<u64>,
+    line_clear_delay: Option<u64>,
+    soft_drop_points: Option<u32>,
+    hard_drop_points: Option<u32>,
+}
+
+/// The rules a game is played by.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rules {
+    pub name: String,
+    /// Whether the 180° rotation is allowed.
+    pub rotate_180: bool,
+    /// Milliseconds between a piece locking and the next one coming in (ARE). Rotations and holds
+    /// pressed in the meantime are applied to the next piece as it comes in.
+    pub entry_delay: u64,
+    /// Milliseconds added to the entry delay when lines are cleared.
+    pub line_clear_delay: u64,
+    /// Points for every row the player soft drops a piece. Rows the piece falls on its own don't count.
+    pub soft_drop_points: u32,
+    /// Points for every row the player hard drops a piece.
+    pub hard_drop_points: u32,
+}
+
+#[derive(Debug)]
+pub enum RulesError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RulesError::Io(e) => write!(f, "could not read rule set: {}", e),
+            RulesError::Parse(e) => write!(f, "could not parse rule set: {}", e),
+        }
+    }
+}
+
+/// Rule sets that ship with the game, available by name.
+pub const BUILTIN: [(&str, &str); 3] = [
+    ("classic", include_str!("../rules/classic.toml")),
+    ("guideline", include_str!("../rules/guideline.toml")),
+    ("modern", include_str!("../rules/modern.toml")),
+];
+
+/// Loads a rule set from a file, or from the built-in rule sets if no such file exists.
+pub fn load(name: &str) -> Result<Rules, RulesError> {
+    if let Some((_, text)) = BUILTIN.iter().find(|(n, _)| *n == name) {
+        if !Path::new(name).exists() {
+            return parse(text, name);
+        }
+    }
+    let text = std::fs::read_to_string(name).map_err(RulesError::Io)?;
+    let stem = Path::new(name)
+        .file_stem()
+        .map_or(name.to_string(), |s| s.to_string_lossy().to_string());
+    parse(&text, &stem)
+}
+
+/// Parses a rule set, naming it `default_name` unless it has a name of its own.
+pub fn parse(text: &str, default_name: &str) -> Result<Rules, RulesError> {
+    let file: RulesFile = toml::from_str(text).map_err(RulesError::Parse)?;
+    let classic = Rules::default();
+    Ok(Rules {
+        name: file.name.unwrap_or_else(|| default_name.to_string()),
+        rotate_180: file.rotate_180.unwrap_or(classic.rotate_180),
+        entry_delay: file.entry_delay.unwrap_or(classic.entry_delay),
+        line_clear_delay: file.line_clear_delay.unwrap_or(classic.line_clear_delay),
+        soft_drop_points: file.soft_drop_points.unwrap_or(classic.soft_drop_points),
+        hard_drop_points: file.hard_drop_points.unwrap_or(classic.hard_drop_points),
+    })
+}
+
+impl Default for Rules {
+    /// The classic rules.
+    fn default() -> Rules {
+        Rules {
+            name: "classic".to_string(),
+            rotate_180: false,
+            entry_delay: 0,
+            line_clear_delay: 0,
+            soft_drop_points: 0,
+            hard_drop_points: 0,
+        }
+    }
+}
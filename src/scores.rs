@@ -1,16 +1,134 @@
+use crate::board::BOARD_HEIGHT;
+use crate::game::Game;
+use crate::input;
+use crate::keymap::Key;
+use crate::piece::PieceBag;
+use crate::render::Renderer;
+use crate::stats::Stats;
+use crate::util::{json_version, Color};
 use dirs::home_dir;
 use serde::{Deserialize, Serialize};
 use std::cmp::{Ord, Ordering};
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::path::Path;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-#[derive(Serialize, Deserialize, PartialEq, Eq)]
+/// Scores kept for each mode.
+const LEADERBOARD_SIZE: usize = 10;
+
+/// Longest name a score can be recorded under, in characters.
+const NAME_LENGTH: usize = 16;
+
+/// How long to wait for a key before drawing again, so that the screen follows terminal resizes.
+const REDRAW: Duration = Duration::from_millis(100);
+
+/// Bumped whenever the format of the scores file changes. Files from before the format had a version are
+/// a plain list of scores, and are migrated the next time a score is saved.
+const VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct ScoresFile {
+    version: u32,
+    scores: Vec<Score>,
+}
+
+impl ScoresFile {
+    fn empty() -> ScoresFile {
+        ScoresFile {
+            version: VERSION,
+            scores: Vec::new(),
+        }
+    }
+}
+
+/// A recorded score. The fields that are options are missing for scores migrated from older files.
+#[derive(Serialize, Deserialize, Clone)]
 struct Score {
     pub name: String,
     pub score: u32,
+    /// The mode the game was played in. Scores recorded before there were modes are marathon scores.
+    #[serde(default = "marathon")]
+    pub mode: String,
+    /// Number of bot hints shown during the game.
+    #[serde(default)]
+    pub hints: u32,
+    /// Missing for scores recorded before statistics were kept.
+    #[serde(default)]
+    pub stats: Option<Stats>,
+    /// When the score was recorded, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub recorded: Option<u64>,
+    #[serde(default)]
+    pub lines: Option<u32>,
+    /// The level the game ended at.
+    #[serde(default)]
+    pub level: Option<u32>,
+    /// Seconds played, not counting pauses.
+    #[serde(default)]
+    pub duration: Option<f64>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// The name of the rule set the game was played by.
+    #[serde(default)]
+    pub rules: Option<String>,
+    /// How the piece sequence was generated.
+    #[serde(default)]
+    pub randomizer: Option<String>,
+    /// The version of the game the score was recorded with.
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+fn marathon() -> String {
+    "marathon".to_string()
+}
+
+impl Score {
+    /// The score `game` ended with, recorded under `name` now.
+    fn new(game: &Game, name: String) -> Score {
+        Score {
+            name,
+            score: game.score,
+            mode: game.mode.name().to_string(),
+            hints: game.hints_used,
+            stats: Some(game.stats.clone()),
+            recorded: Some(now()),
+            lines: Some(game.lines),
+            level: Some(game.level),
+            duration: Some(game.stats.duration),
+            seed: Some(game.seed),
+            rules: Some(game.rules.name.clone()),
+            randomizer: Some(PieceBag::RANDOMIZER.to_string()),
+            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        }
+    }
+
+    /// The score as a line of a leaderboard.
+    fn describe(&self) -> String {
+        let mut line = format!("{:<16} {:>7}", self.name, self.score);
+        if let Some(lines) = self.lines {
+            line += &format!(" {:>4} lines", lines);
+        }
+        if let Some(recorded) = self.recorded {
+            line += &format!("  {}", date(recorded));
+        }
+        if self.hints > 0 {
+            line += &format!("  ({} hints)", self.hints);
+        }
+        line
+    }
+}
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.score == other.score
+    }
 }
 
+impl Eq for Score {}
+
 impl Ord for Score {
     fn cmp(&self, other: &Self) -> Ordering {
         self.score.cmp(&other.score)
@@ -19,70 +137,317 @@ impl Ord for Score {
 
 impl PartialOrd for Score {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.score.partial_cmp(&other.score)
+        Some(self.cmp(other))
     }
 }
 
-fn load_scores(path: &Path) -> Vec<Score> {
-    let file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(path)
-        .unwrap();
-    if let Ok(scores) = serde_json::from_reader(file) {
-        return scores;
-    } else {
-        return Vec::new();
+#[derive(Debug)]
+pub enum ScoresError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    Version(u32),
+}
+
+impl fmt::Display for ScoresError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScoresError::Io(e) => write!(f, "could not read scores: {}", e),
+            ScoresError::Parse(e) => write!(f, "scores file is corrupt: {}", e),
+            ScoresError::Version(v) => write!(
+                f,
+                "scores file has format version {}, but this version of the game only reads up to version {}",
+                v, VERSION
+            ),
+        }
     }
 }
 
-fn write_scores(path: &Path, scores: Vec<Score>) {
-    let ser = serde_json::to_string(&scores).unwrap();
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(path)
-        .unwrap();
-    file.write_all(ser.as_bytes()).unwrap();
-    file.flush().unwrap();
+/// Reads the scores file. A file from before the format had a version is read as version 0. There are no
+/// scores yet if the file doesn't exist or is empty.
+fn load_scores(path: &Path) -> Result<ScoresFile, ScoresError> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ScoresFile::empty()),
+        Err(e) => return Err(ScoresError::Io(e)),
+    };
+    if text.trim().is_empty() {
+        return Ok(ScoresFile::empty());
+    }
+    if let Ok(scores) = serde_json::from_str::<Vec<Score>>(&text) {
+        return Ok(ScoresFile { version: 0, scores });
+    }
+    let version = json_version(&text).map_err(ScoresError::Parse)?;
+    if version > VERSION {
+        return Err(ScoresError::Version(version));
+    }
+    serde_json::from_str(&text).map_err(ScoresError::Parse)
 }
 
-fn ask_username() -> String {
-    println!("Name:");
-    //std::io::stdout().flush().unwrap();
-    let mut buffer = String::new();
-    std::io::stdin().read_line(&mut buffer).unwrap();
-    buffer = buffer.trim().into();
-    return buffer;
+/// Writes `scores` in the current format, which migrates an older file.
+fn write_scores(path: &Path, scores: Vec<Score>) -> io::Result<()> {
+    let file = ScoresFile {
+        version: VERSION,
+        scores,
+    };
+    std::fs::write(path, serde_json::to_string(&file)?)
 }
 
-pub fn manage_highscore(pscore: u32) {
-    let mut path = home_dir().unwrap();
-    path.push(".tetris");
-    let mut scores = load_scores(path.as_path());
-    if scores.iter().any(|s| pscore > s.score) || scores.is_empty() {
-        println!("Your score: {}", pscore);
-        let name = ask_username();
-        scores.push(Score {
-            name: name,
-            score: pscore,
-        });
-        scores.sort();
-        scores.reverse();
-        scores.truncate(10);
-        write_scores(path.as_path(), scores);
-    }
-    print_highscores();
-}
-
-pub fn print_highscores() {
+/// Moves a scores file that can't be read out of the way, so that new scores can be recorded without
+/// losing the old ones. Returns where the file was moved.
+fn back_up(path: &Path) -> io::Result<PathBuf> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".corrupt-{}", now()));
+    std::fs::rename(path, &backup)?;
+    Ok(backup.into())
+}
+
+fn scores_path() -> PathBuf {
     let mut path = home_dir().unwrap();
     path.push(".tetris");
-    let scores = load_scores(path.as_path());
-    print!("{}", termion::clear::BeforeCursor);
-    scores
+    path
+}
+
+/// Seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// The date, in UTC, `seconds` after the Unix epoch as year-month-day.
+fn date(seconds: u64) -> String {
+    // Howard Hinnant's civil_from_days, with years starting in March so that leap days come last
+    let days = (seconds / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let (year, month) = if month < 10 {
+        (year_of_era + era * 400, month + 3)
+    } else {
+        (year_of_era + era * 400 + 1, month - 9)
+    };
+    format!("{}-{:02}-{:02}", year, month, day)
+}
+
+/// Asks for the name to record a high score of `pscore` under, starting from `default`. Returns None if
+/// the player chose not to record it.
+fn enter_name(display: &mut impl Renderer, pscore: u32, default: &str) -> Option<String> {
+    let mut name: String = default.chars().take(NAME_LENGTH).collect();
+    // Keys still typed after the game ended, such as a hard drop too many, aren't meant as the name
+    while input::read_key(Some(Duration::from_millis(0))).is_some() {}
+    loop {
+        display.clear_buffer();
+        let score = format!("New high score: {}", pscore);
+        display.set_text(score, 2, 1, Color::Highlight, Color::Black);
+        let field = format!("Name: {}_", name);
+        display.set_text(field, 2, 3, Color::Text, Color::Black);
+        let help = "Enter: save  Esc: don't record";
+        display.set_text(help, 2, BOARD_HEIGHT, Color::Text, Color::Black);
+        display.render();
+
+        match input::read_key(Some(REDRAW)) {
+            Some(Key::Enter) if !name.trim().is_empty() => return Some(name.trim().to_string()),
+            Some(Key::Esc) => return None,
+            Some(Key::Backspace) => {
+                name.pop();
+            }
+            Some(Key::Ctrl('u')) => name.clear(),
+            Some(Key::Char(c)) if !c.is_control() && name.chars().count() < NAME_LENGTH => {
+                name.push(c)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Records the score of `game` if it makes the leaderboard of its mode, asking for the name on the display
+/// with `name` as the default, and then shows the leaderboard until a key is pressed. `name` is set to the
+/// name entered. A corrupt scores file is backed up and replaced by a new one.
+pub fn manage_highscore(display: &mut impl Renderer, game: &Game, name: &mut String) {
+    let path = scores_path();
+    let mode = game.mode.name();
+    let mut notice = String::new();
+    let file = match load_scores(&path) {
+        Ok(file) => Some(file),
+        Err(ScoresError::Parse(_)) => match back_up(&path) {
+            Ok(backup) => {
+                notice = format!("Corrupt scores file moved to\n{}", backup.display());
+                Some(ScoresFile::empty())
+            }
+            Err(e) => {
+                notice = format!("Could not back up the corrupt scores file: {}", e);
+                None
+            }
+        },
+        Err(e) => {
+            notice = e.to_string();
+            None
+        }
+    };
+
+    let mut rank = None;
+    if let Some(ScoresFile {
+        version,
+        mut scores,
+    }) = file
+    {
+        let board: Vec<&Score> = scores.iter().filter(|s| s.mode == mode).collect();
+        let qualifies =
+            board.len() < LEADERBOARD_SIZE || board.iter().any(|s| game.score > s.score);
+        let entered = if qualifies {
+            enter_name(display, game.score, name)
+        } else {
+            None
+        };
+        // An older file is migrated even if the score doesn't make it in
+        let changed = entered.is_some() || version < VERSION;
+        if let Some(entered) = entered {
+            // Sorting is stable, so after reversing the new score comes before the scores it ties with
+            rank = Some(board.iter().filter(|s| s.score > game.score).count());
+            *name = entered.clone();
+            scores.push(Score::new(game, entered));
+            scores.sort();
+            scores.reverse();
+            let mut kept: HashMap<String, usize> = HashMap::new();
+            scores.retain(|s| {
+                let count = kept.entry(s.mode.clone()).or_insert(0);
+                *count += 1;
+                *count <= LEADERBOARD_SIZE
+            });
+        }
+        if changed {
+            if let Err(e) = write_scores(&path, scores) {
+                notice = format!("Could not save the score: {}", e);
+            }
+        }
+    }
+
+    let leaderboard = leaderboard(mode);
+    loop {
+        display.clear_buffer();
+        let title = format!("Highscores ({})", mode);
+        display.set_text(title, 2, 1, Color::Highlight, Color::Black);
+        match &leaderboard {
+            Ok(lines) => {
+                for (i, line) in lines.iter().enumerate() {
+                    let color = if rank == Some(i) {
+                        Color::Highlight
+                    } else {
+                        Color::Text
+                    };
+                    let line = format!("{:>2}. {}", i + 1, line);
+                    display.set_text(line, 2, 3 + i as u32, color, Color::Black);
+                }
+            }
+            Err(e) => display.set_text(e.to_string(), 2, 3, Color::Text, Color::Black),
+        }
+        let score = format!("Your score: {}", game.score);
+        let y = 4 + LEADERBOARD_SIZE as u32;
+        display.set_text(score, 2, y, Color::Text, Color::Black);
+        for (i, line) in notice.lines().enumerate() {
+            display.set_text(line, 2, y + 2 + i as u32, Color::Highlight, Color::Black);
+        }
+        let help = "Press any key to continue";
+        display.set_text(help, 2, BOARD_HEIGHT, Color::Highlight, Color::Black);
+        display.render();
+        if input::read_key(Some(REDRAW)).is_some() {
+            return;
+        }
+    }
+}
+
+/// The leaderboard of `mode`, one score per line, best first.
+pub fn leaderboard(mode: &str) -> Result<Vec<String>, ScoresError> {
+    Ok(load_scores(&scores_path())?
+        .scores
         .iter()
-        .for_each(|score| println!("{0}: {1}", score.name, score.score));
+        .filter(|s| s.mode == mode)
+        .map(Score::describe)
+        .collect())
+}
+
+/// Prints the leaderboards of `modes`.
+pub fn print_highscores(modes: &[&str]) {
+    print!("{}", termion::clear::BeforeCursor);
+    for mode in modes {
+        println!("Highscores ({}):", mode);
+        match leaderboard(mode) {
+            Ok(lines) => lines.iter().for_each(|l| println!("{}", l)),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scores file in a directory of its own, removed with the directory when the test is done.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, text: &str) -> TempFile {
+            let mut dir = std::env::temp_dir();
+            dir.push(format!("tetris-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let file = TempFile(dir.join("scores"));
+            std::fs::write(&file.0, text).unwrap();
+            file
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(self.0.parent().unwrap());
+        }
+    }
+
+    #[test]
+    fn old_file_is_migrated_when_written() {
+        let file = TempFile::new("migrate", r#"[{"name":"old","score":5}]"#);
+        let old = load_scores(&file.0).unwrap();
+        assert_eq!(old.version, 0);
+        assert_eq!(old.scores[0].mode, "marathon");
+        // Reading leaves the file as it was
+        assert!(std::fs::read_to_string(&file.0).unwrap().starts_with('['));
+
+        write_scores(&file.0, old.scores).unwrap();
+        let migrated = load_scores(&file.0).unwrap();
+        assert_eq!(migrated.version, VERSION);
+        assert_eq!(migrated.scores.len(), 1);
+        assert_eq!(migrated.scores[0].describe(), "old                    5");
+    }
+
+    #[test]
+    fn missing_or_empty_file_has_no_scores() {
+        let file = TempFile::new("empty", " \n");
+        assert!(load_scores(&file.0).unwrap().scores.is_empty());
+        std::fs::remove_file(&file.0).unwrap();
+        assert!(load_scores(&file.0).unwrap().scores.is_empty());
+    }
+
+    #[test]
+    fn newer_file_is_refused() {
+        let file = TempFile::new("newer", r#"{"version":9,"scores":[]}"#);
+        assert!(matches!(load_scores(&file.0), Err(ScoresError::Version(9))));
+    }
+
+    #[test]
+    fn corrupt_file_is_backed_up() {
+        let file = TempFile::new("corrupt", "{not json");
+        assert!(matches!(load_scores(&file.0), Err(ScoresError::Parse(_))));
+        let backup = back_up(&file.0).unwrap();
+        assert!(!file.0.exists());
+        assert!(backup
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("scores.corrupt-"));
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "{not json");
+        assert!(load_scores(&file.0).unwrap().scores.is_empty());
+    }
 }
@@ -0,0 +1,85 @@
+use crate::hud::Hud;
+use dirs::home_dir;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The settings picked in the menu, remembered between games. Command line options override them.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Settings {
+    /// Rule set file or built-in rule set.
+    pub rules: String,
+    /// Theme file or built-in theme.
+    pub theme: String,
+    /// How cells are drawn, as named by `--style`.
+    pub style: String,
+    pub box_borders: bool,
+    pub animations: bool,
+    /// The name the last high score was recorded under, offered for the next one.
+    pub name: String,
+    /// The live statistics shown in the sidebar, also toggled in game.
+    pub hud: Hud,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            rules: "classic".to_string(),
+            theme: "classic".to_string(),
+            style: "blocks".to_string(),
+            box_borders: false,
+            animations: true,
+            name: String::new(),
+            hud: Hud::default(),
+        }
+    }
+}
+
+/// Location of the settings file, `tetris/settings.json` in the user's config directory, next to the keymap.
+pub fn path() -> Option<PathBuf> {
+    let mut path = dirs::config_dir()?;
+    path.push("tetris");
+    path.push("settings.json");
+    Some(path)
+}
+
+fn read<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    File::open(path)
+        .ok()
+        .and_then(|f| serde_json::from_reader(f).ok())
+}
+
+impl Settings {
+    /// Loads the saved settings, or the defaults if none were saved yet. Settings saved by older versions,
+    /// which kept them and the HUD fields in files of their own in the home directory, are read if there
+    /// are no others.
+    pub fn load() -> Settings {
+        if let Some(settings) = path().and_then(|p| read(&p)) {
+            return settings;
+        }
+        let home = home_dir().unwrap_or_default();
+        let mut settings: Settings = read(&home.join(".tetris_settings")).unwrap_or_default();
+        if let Some(hud) = read(&home.join(".tetris_hud")) {
+            settings.hud = hud;
+        }
+        settings
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = path().ok_or_else(|| io::Error::other("no config directory"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_string(self)?)
+    }
+
+    /// Changes the saved settings with `edit`, leaving the settings it doesn't touch as they were saved.
+    pub fn update(edit: impl FnOnce(&mut Settings)) -> io::Result<()> {
+        let mut settings = Settings::load();
+        edit(&mut settings);
+        settings.save()
+    }
+}
@@ -0,0 +1,85 @@
+use crate::board::*;
+use crate::piece::{BagState, PieceKind};
+use crate::rules::Rules;
+use crate::stats::Stats;
+use crate::util::json_version;
+use dirs::home_dir;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Bumped whenever the snapshot format changes. Snapshots of any other version are rejected.
+pub const VERSION: u32 = 1;
+
+/// Everything needed to continue a marathon game exactly where it was left.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub board: Board,
+    pub piece: PieceKind,
+    /// The active piece's shape, which records its rotation.
+    pub shape: Vec<Vec<u8>>,
+    pub position: Point,
+    pub hold: Option<PieceKind>,
+    pub switched: bool,
+    pub bag: BagState,
+    pub seed: u64,
+    pub score: u32,
+    pub lines: u32,
+    pub level: u32,
+    pub to_clear: i32,
+    /// Milliseconds between gravity ticks.
+    pub speed: u64,
+    pub combo: u32,
+    pub cleared_last_round: u32,
+    pub hints_used: u32,
+    #[serde(default)]
+    pub stats: Stats,
+    #[serde(default)]
+    pub rules: Rules,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    Version(u32),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "could not read saved game: {}", e),
+            SnapshotError::Parse(e) => write!(f, "saved game is corrupt: {}", e),
+            SnapshotError::Version(v) => write!(
+                f,
+                "saved game has format version {}, but this version of the game can only resume version {}",
+                v, VERSION
+            ),
+        }
+    }
+}
+
+/// Default location of the saved game, next to the highscore file.
+pub fn path() -> PathBuf {
+    let mut path = home_dir().unwrap();
+    path.push(".tetris_save");
+    path
+}
+
+pub fn load(path: &Path) -> Result<Snapshot, SnapshotError> {
+    let text = std::fs::read_to_string(path).map_err(SnapshotError::Io)?;
+    let version = json_version(&text).map_err(SnapshotError::Parse)?;
+    if version != VERSION {
+        return Err(SnapshotError::Version(version));
+    }
+    serde_json::from_str(&text).map_err(SnapshotError::Parse)
+}
+
+impl Snapshot {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let ser = serde_json::to_string(self)?;
+        std::fs::write(path, ser)
+    }
+}
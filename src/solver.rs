@@ -0,0 +1,350 @@
+use crate::board::*;
+use crate::piece::*;
+use crate::util::Direction;
+use std::collections::HashSet;
+
+/// Perfect clears are only searched for within this many lines from the floor.
+pub const MAX_PC_HEIGHT: usize = 4;
+
+/// Positions searched at most for one chance, which keeps the search to a few milliseconds as it runs
+/// whenever a piece comes in. The chance is unknown if the search needs more.
+const MAX_NODES: usize = 10_000;
+
+/// The cells of one orientation of a piece, relative to its top-left corner.
+type Shape = Vec<(usize, usize)>;
+
+/// The bottom `height` lines of a board, top line first, one bit per column.
+#[derive(Copy, Clone)]
+struct Field {
+    rows: [u16; MAX_PC_HEIGHT],
+    height: usize,
+}
+
+const FULL_ROW: u16 = (1 << BOARD_WIDTH) - 1;
+
+impl Field {
+    fn key(&self) -> u64 {
+        let mut key = self.height as u64;
+        for row in &self.rows[..self.height] {
+            key = (key << BOARD_WIDTH) | *row as u64;
+        }
+        key
+    }
+
+    fn collides(&self, shape: &Shape, x: usize, y: usize) -> bool {
+        shape
+            .iter()
+            .any(|&(r, c)| y + r >= self.height || self.rows[y + r] & (1 << (x + c)) != 0)
+    }
+
+    /// Hard drops `shape` in column `x` and clears any lines it completes. Returns None if the piece doesn't
+    /// fit inside the field.
+    fn drop(&self, shape: &Shape, x: usize) -> Option<Field> {
+        if self.collides(shape, x, 0) {
+            return None;
+        }
+        let mut y = 0;
+        while !self.collides(shape, x, y + 1) {
+            y += 1;
+        }
+
+        let mut rows = self.rows;
+        for &(r, c) in shape {
+            rows[y + r] |= 1 << (x + c);
+        }
+
+        let mut field = Field {
+            rows: [0; MAX_PC_HEIGHT],
+            height: 0,
+        };
+        for &row in rows[..self.height].iter().filter(|&&row| row != FULL_ROW) {
+            field.rows[field.height] = row;
+            field.height += 1;
+        }
+        Some(field)
+    }
+
+    /// A field can only be cleared if every enclosed area of empty cells can hold whole pieces.
+    fn fillable(&self) -> bool {
+        let mut seen = [0u16; MAX_PC_HEIGHT];
+        for y in 0..self.height {
+            for x in 0..BOARD_WIDTH as usize {
+                if (self.rows[y] | seen[y]) & (1 << x) != 0 {
+                    continue;
+                }
+                let mut size = 0usize;
+                let mut stack = vec![(y, x)];
+                seen[y] |= 1 << x;
+                while let Some((y, x)) = stack.pop() {
+                    size += 1;
+                    let mut visit = |y: usize, x: usize| {
+                        if (self.rows[y] | seen[y]) & (1 << x) == 0 {
+                            seen[y] |= 1 << x;
+                            stack.push((y, x));
+                        }
+                    };
+                    if y > 0 {
+                        visit(y - 1, x);
+                    }
+                    if y + 1 < self.height {
+                        visit(y + 1, x);
+                    }
+                    if x > 0 {
+                        visit(y, x - 1);
+                    }
+                    if x + 1 < BOARD_WIDTH as usize {
+                        visit(y, x + 1);
+                    }
+                }
+                if !size.is_multiple_of(4) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+fn shapes(kind: PieceKind) -> Vec<Shape> {
+    let mut piece = Piece::new(kind);
+    let mut shapes: Vec<Shape> = Vec::new();
+    for _ in 0..4 {
+        let mut cells = Vec::new();
+        piece.each_point(&mut |row, col| cells.push((row as usize, col as usize)));
+        let top = cells.iter().map(|c| c.0).min().unwrap();
+        let left = cells.iter().map(|c| c.1).min().unwrap();
+        let mut shape: Shape = cells.iter().map(|&(r, c)| (r - top, c - left)).collect();
+        shape.sort_unstable();
+        if !shapes.contains(&shape) {
+            shapes.push(shape);
+        }
+        piece.rotate(Direction::Right);
+    }
+    shapes
+}
+
+struct Solver {
+    shapes: Vec<Vec<Shape>>,
+    /// Positions already known to fail, keyed by field, remaining queue and hold.
+    failed: HashSet<(u64, Vec<PieceKind>, Option<PieceKind>)>,
+    /// Positions that may still be searched before giving up.
+    budget: usize,
+}
+
+impl Solver {
+    /// Returns true if `field` can be perfect cleared using the pieces in `queue` (in order) plus the held
+    /// piece, using hold at most once per piece like the game does. Also false once the budget runs out.
+    fn solve(&mut self, field: Field, queue: &[PieceKind], hold: Option<PieceKind>) -> bool {
+        if field.height == 0 {
+            return true;
+        }
+        if (queue.is_empty() && hold.is_none()) || self.budget == 0 {
+            return false;
+        }
+        self.budget -= 1;
+        let key = (field.key(), queue.to_vec(), hold);
+        if self.failed.contains(&key) {
+            return false;
+        }
+
+        // Every option is a piece to place together with the queue and hold that remain afterwards.
+        let mut options = Vec::new();
+        if let Some((&first, rest)) = queue.split_first() {
+            options.push((first, rest, hold));
+            match hold {
+                Some(h) => options.push((h, rest, Some(first))),
+                None => {
+                    if let Some((&second, rest)) = rest.split_first() {
+                        options.push((second, rest, Some(first)));
+                    }
+                }
+            }
+        } else if let Some(h) = hold {
+            options.push((h, queue, None));
+        }
+
+        for (kind, rest, hold) in options {
+            for shape_index in 0..self.shapes[kind as usize].len() {
+                let shape = self.shapes[kind as usize][shape_index].clone();
+                let width = shape.iter().map(|c| c.1).max().unwrap() + 1;
+                for x in 0..=(BOARD_WIDTH as usize - width) {
+                    if let Some(next) = field.drop(&shape, x) {
+                        if next.fillable() && self.solve(next, rest, hold) {
+                            return true;
+                        }
+                    }
+                }
+            }
+        }
+
+        if self.budget > 0 {
+            self.failed.insert(key);
+        }
+        false
+    }
+}
+
+/// Returns every distinct ordering of `kinds`.
+fn orderings(kinds: &[PieceKind]) -> Vec<Vec<PieceKind>> {
+    if kinds.is_empty() {
+        return vec![Vec::new()];
+    }
+    let mut result = Vec::new();
+    for i in 0..kinds.len() {
+        if kinds[..i].contains(&kinds[i]) {
+            continue;
+        }
+        let mut rest = kinds.to_vec();
+        let first = rest.remove(i);
+        for mut tail in orderings(&rest) {
+            tail.insert(0, first);
+            result.push(tail);
+        }
+    }
+    result
+}
+
+/// Works out the chance that `board` can be perfect cleared with the current, held and next pieces plus
+/// the rest of the current bag. If `rest` is in a known order, as the queue of an opener is, that order is
+/// the only one tried; otherwise every order the rest of the bag could come in is, by brute force. Returns
+/// None if the stack is too tall, there aren't enough pieces left in the bag for a perfect clear or the
+/// search would take too long.
+pub fn pc_probability(
+    board: &Board,
+    current: PieceKind,
+    hold: Option<PieceKind>,
+    next: PieceKind,
+    rest: &[PieceKind],
+    known_order: bool,
+) -> Option<f64> {
+    let stack_height = board
+        .cells
+        .iter()
+        .position(|row| row.iter().any(|c| c.is_some()))
+        .map_or(0, |top| BOARD_HEIGHT as usize - top);
+    if stack_height > MAX_PC_HEIGHT {
+        return None;
+    }
+
+    let available = 2 + rest.len() + hold.map_or(0, |_| 1);
+    let filled = |height: usize| -> usize {
+        board.cells[BOARD_HEIGHT as usize - height..]
+            .iter()
+            .map(|row| row.iter().filter(|c| c.is_some()).count())
+            .sum()
+    };
+    // The lowest perfect clear height that leaves room for whole pieces and needs no more pieces than are
+    // available.
+    let height = (stack_height.max(1)..=MAX_PC_HEIGHT).find(|&h| {
+        let empty = h * BOARD_WIDTH as usize - filled(h);
+        empty.is_multiple_of(4) && empty / 4 <= available
+    })?;
+
+    let mut field = Field {
+        rows: [0; MAX_PC_HEIGHT],
+        height,
+    };
+    for (y, row) in board.cells[BOARD_HEIGHT as usize - height..]
+        .iter()
+        .enumerate()
+    {
+        for (x, cell) in row.iter().enumerate() {
+            if cell.is_some() {
+                field.rows[y] |= 1 << x;
+            }
+        }
+    }
+    if !field.fillable() {
+        return Some(0.0);
+    }
+
+    let mut solver = Solver {
+        shapes: PieceKind::ALL.iter().map(|&k| shapes(k)).collect(),
+        failed: HashSet::new(),
+        budget: MAX_NODES,
+    };
+    let orders = if known_order {
+        vec![rest.to_vec()]
+    } else {
+        orderings(rest)
+    };
+    let solved = orders
+        .iter()
+        .filter(|order| {
+            let mut queue = vec![current, next];
+            queue.extend(order.iter());
+            solver.solve(field, &queue, hold)
+        })
+        .count();
+    if solver.budget == 0 {
+        return None;
+    }
+
+    Some(solved as f64 / orders.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::PieceKind::*;
+    use crate::util::Color;
+
+    /// A board whose bottom `height` lines are full except for the last column.
+    fn well(height: usize) -> Board {
+        let mut board = Board::new();
+        for row in board.cells.iter_mut().rev().take(height) {
+            row.iter_mut()
+                .take(BOARD_WIDTH as usize - 1)
+                .for_each(|c| *c = Some(Color::Gray));
+        }
+        board
+    }
+
+    #[test]
+    fn i_in_a_well_is_a_perfect_clear() {
+        assert_eq!(pc_probability(&well(4), I, None, O, &[], true), Some(1.0));
+        // With the I held or next, as long as the other piece can go somewhere
+        assert_eq!(
+            pc_probability(&well(4), O, Some(I), T, &[], true),
+            Some(1.0)
+        );
+    }
+
+    #[test]
+    fn well_without_an_i_is_impossible() {
+        assert_eq!(pc_probability(&well(4), O, None, S, &[], true), Some(0.0));
+        assert_eq!(
+            pc_probability(&well(4), O, None, S, &[T, Z, L], false),
+            Some(0.0)
+        );
+    }
+
+    #[test]
+    fn too_tall_a_stack_has_no_chance() {
+        assert_eq!(pc_probability(&well(5), I, None, O, &[], true), None);
+    }
+
+    #[test]
+    fn known_order_is_the_only_one_tried() {
+        // A hole four wide and two high, which the I and Z can only fill with a second I after the Z is held
+        let mut board = well(2);
+        for row in board.cells.iter_mut().rev().take(2) {
+            row.iter_mut().skip(6).for_each(|c| *c = None);
+        }
+        let chance =
+            |rest: &[PieceKind], known_order| pc_probability(&board, I, None, Z, rest, known_order);
+        assert_eq!(chance(&[I, O], true), Some(1.0));
+        assert_eq!(chance(&[O, I], true), Some(0.0));
+        assert_eq!(chance(&[O, I], false), Some(0.5));
+    }
+
+    #[test]
+    fn long_search_gives_up() {
+        let rest = [O, I, L, S, T, I];
+        assert_eq!(
+            pc_probability(&Board::new(), J, Some(I), Z, &rest, false),
+            None
+        );
+        assert!(pc_probability(&Board::new(), J, Some(I), Z, &rest, true).is_some());
+    }
+}
@@ -0,0 +1,151 @@
+use crate::piece::PieceKind;
+use serde::{Deserialize, Serialize};
+
+/// Extra lines of attack for each combo count, as in the guideline's multiplayer games.
+const COMBO_ATTACK: [u32; 11] = [0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+
+/// Statistics of a single game, shown when it ends and stored with its score.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(default)]
+pub struct Stats {
+    pub pieces: u32,
+    /// Movement, rotation, drop and hold key presses.
+    pub keys: u32,
+    /// Line clears without a T-spin, indexed by the number of lines cleared.
+    pub clears: [u32; 5],
+    /// T-spins, indexed by the number of lines cleared.
+    pub tspins: [u32; 4],
+    pub max_combo: u32,
+    /// The longest run of back-to-back tetrises and T-spin clears, not counting the first clear of the run.
+    pub max_b2b: u32,
+    /// Garbage lines these clears would send in a multiplayer game.
+    pub attack: u32,
+    /// Pieces placed of every kind, in the order of `PieceKind::ALL`.
+    pub distribution: [u32; 7],
+    /// Seconds played, not counting pauses.
+    pub duration: f64,
+    /// Back-to-back clears in the current run, saved so that a resumed game carries the run on.
+    b2b_run: u32,
+}
+
+impl Stats {
+    /// Records a piece that locked, clearing `cleared` lines.
+    pub fn locked(
+        &mut self,
+        kind: PieceKind,
+        cleared: u32,
+        tspin: bool,
+        combo: u32,
+        perfect_clear: bool,
+    ) {
+        self.pieces += 1;
+        self.distribution[PieceKind::ALL.iter().position(|&k| k == kind).unwrap()] += 1;
+        self.max_combo = self.max_combo.max(combo);
+
+        if tspin {
+            self.tspins[cleared.min(3) as usize] += 1;
+        } else {
+            self.clears[cleared as usize] += 1;
+        }
+        if cleared == 0 {
+            return;
+        }
+        if tspin || cleared == 4 {
+            self.b2b_run += 1;
+            self.max_b2b = self.max_b2b.max(self.b2b_run - 1);
+        } else {
+            self.b2b_run = 0;
+        }
+
+        self.attack += match (tspin, cleared) {
+            (true, n) => 2 * n,
+            (false, 4) => 4,
+            (false, n) => n - 1,
+        };
+        if self.b2b_run > 1 {
+            self.attack += 1;
+        }
+        self.attack += COMBO_ATTACK[(combo as usize).min(COMBO_ATTACK.len() - 1)];
+        if perfect_clear {
+            self.attack += 10;
+        }
+    }
+
+    /// Pieces per second over `seconds` of play.
+    pub fn pieces_per_second(&self, seconds: f64) -> f64 {
+        if seconds > 0.0 {
+            self.pieces as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// Attack per minute over `seconds` of play.
+    pub fn attack_per_minute(&self, seconds: f64) -> f64 {
+        if seconds > 0.0 {
+            self.attack as f64 * 60.0 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    pub fn keys_per_piece(&self) -> f64 {
+        if self.pieces > 0 {
+            self.keys as f64 / self.pieces as f64
+        } else {
+            0.0
+        }
+    }
+
+    /// Describes the stats in a few lines of text.
+    pub fn summary(&self) -> Vec<String> {
+        let seconds = self.duration as u64;
+        let distribution: Vec<String> = PieceKind::ALL
+            .iter()
+            .zip(self.distribution.iter())
+            .map(|(k, n)| format!("{} {}", k.name(), n))
+            .collect();
+        vec![
+            format!("Duration: {}:{:02}", seconds / 60, seconds % 60),
+            format!(
+                "Pieces: {} ({:.2} PPS, {:.2} keys per piece)",
+                self.pieces,
+                self.pieces_per_second(self.duration),
+                self.keys_per_piece()
+            ),
+            format!(
+                "Singles: {}  Doubles: {}  Triples: {}  Tetrises: {}",
+                self.clears[1], self.clears[2], self.clears[3], self.clears[4]
+            ),
+            format!(
+                "T-spins: {} without lines, {} singles, {} doubles, {} triples",
+                self.tspins[0], self.tspins[1], self.tspins[2], self.tspins[3]
+            ),
+            format!(
+                "Attack: {} ({:.1} APM)",
+                self.attack,
+                self.attack_per_minute(self.duration)
+            ),
+            format!("Max combo: {}  Max B2B: {}", self.max_combo, self.max_b2b),
+            format!("Pieces: {}", distribution.join("  ")),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn back_to_back_run_survives_saving() {
+        let mut stats = Stats::default();
+        stats.locked(PieceKind::I, 4, false, 0, false);
+        stats.locked(PieceKind::I, 4, false, 0, false);
+        let mut resumed: Stats =
+            serde_json::from_str(&serde_json::to_string(&stats).unwrap()).unwrap();
+        resumed.locked(PieceKind::I, 4, false, 0, false);
+        assert_eq!(resumed.max_b2b, 2);
+        // Two back-to-back bonuses on top of three tetrises
+        assert_eq!(resumed.attack, 3 * 4 + 2);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::render::{self, Frame, Renderer};
+use crate::theme::Theme;
+
+/// Renders to plain text, for printing boards and for comparing frames in tests. It uses the monochrome
+/// theme, so every kind of cell can be told apart without colour.
+pub struct TextDisplay {
+    frame: Frame,
+    text: String,
+    /// The size of the terminal the text stands in for, if it isn't just the frame's size.
+    size: Option<(u16, u16)>,
+}
+
+impl TextDisplay {
+    pub fn new(width: u32, height: u32) -> TextDisplay {
+        let mut frame = Frame::new(width, height);
+        frame.theme = Theme::monochrome();
+        TextDisplay {
+            frame,
+            text: String::new(),
+            size: None,
+        }
+    }
+
+    /// Renders as if on a terminal of `size`, which shows a notice instead of the frame if it is too small.
+    #[cfg(test)]
+    pub fn resize(&mut self, size: (u16, u16)) {
+        self.size = Some(size);
+    }
+
+    /// The frame shown by the last call to `render`, one line per row without trailing spaces.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl Renderer for TextDisplay {
+    fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        &mut self.frame
+    }
+
+    fn render(&mut self) {
+        if let Some(size) = self.size.filter(|_| !self.fits()) {
+            self.text = render::too_small(&self.frame, size)
+                .iter()
+                .map(|line| line.clone() + "\n")
+                .collect();
+            return;
+        }
+        self.text = self
+            .frame
+            .rows()
+            .iter()
+            .map(|row| {
+                let line: String = row.iter().map(|p| p.c).collect();
+                line.trim_end().to_string() + "\n"
+            })
+            .collect();
+    }
+
+    fn fits(&self) -> bool {
+        let (width, height) = self.frame.size();
+        self.size.is_none_or(|(w, h)| w >= width && h >= height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::BOARD_WIDTH;
+    use crate::game::{Game, Mode};
+    use crate::render::CellStyle;
+    use crate::util::Color;
+    use crate::{FRAME_HEIGHT, FRAME_WIDTH};
+
+    /// A marathon game on a board with a few garbage lines. Its first piece is held and the second, a T, is
+    /// at the top.
+    fn game() -> Game {
+        let mut game = Game::with_seed(Mode::Marathon, 1);
+        for (y, line) in ["X........X", "XX..XXXXXX", "XXX.XXXXXX"]
+            .iter()
+            .enumerate()
+        {
+            for (x, c) in line.chars().enumerate() {
+                if c == 'X' {
+                    game.board.cells[17 + y][x] = Some(Color::Gray);
+                }
+            }
+        }
+        game.switch_hold();
+        game
+    }
+
+    fn render(game: &Game, display: &mut TextDisplay) -> String {
+        display.clear_buffer();
+        game.render(display);
+        display.render();
+        display.text().to_string()
+    }
+
+    /// The text of a frame with `lines` as its rows.
+    fn text(lines: &[&str]) -> String {
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    #[test]
+    fn board_piece_ghost_and_hud() {
+        let mut display = TextDisplay::new(FRAME_WIDTH, FRAME_HEIGHT);
+        let expected = text(&[
+            "         %%",
+            "       %%%%%%",
+            "|                    |",
+            "|                    |   Level: 1            Time: 0:00.0",
+            "|                    |                       PPS: 0.00",
+            "|                    |   Score: 0            APM: 0.0",
+            "|                    |                       KPP: 0.00",
+            "|                    |   Combo: 0            Lines: 0",
+            "|                    |",
+            "|                    |   Next piece:",
+            "|                    |",
+            "|                    |     @@@@",
+            "|                    |     @@@@",
+            "|                    |   Holding:",
+            "|                    |",
+            "|                    |",
+            "|        ..          |     ########",
+            "|XX    ......      XX|",
+            "|XXXX    XXXXXXXXXXXX|",
+            "|XXXXXX  XXXXXXXXXXXX|",
+            "---------------------",
+            "",
+        ]);
+        assert_eq!(render(&game(), &mut display), expected);
+    }
+
+    #[test]
+    fn small_cells() {
+        let mut display = TextDisplay::new(FRAME_WIDTH, FRAME_HEIGHT);
+        display.frame_mut().theme = Theme::default();
+        display.frame_mut().cell_style = CellStyle::Small;
+        let expected = text(&[
+            "       ▀▀▀▀▀▀",
+            "|                    |",
+            "|                    |",
+            "|                    |   Level: 1            Time: 0:00.0",
+            "|                    |                       PPS: 0.00",
+            "|                    |   Score: 0            APM: 0.0",
+            "|                    |                       KPP: 0.00",
+            "|                    |   Combo: 0            Lines: 0",
+            "|▀▀    ▀▀▀▀▀▀      ▀▀|",
+            "|▀▀▀▀▀▀  ▀▀▀▀▀▀▀▀▀▀▀▀|   Next piece:",
+            "---------------------",
+            "",
+            "",
+            "                         Holding:",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+        ]);
+        assert_eq!(render(&game(), &mut display), expected);
+
+        // Line 8 has playfield row 16 in its upper half and row 17 in its lower half: the top of the ghost
+        // and the garbage on its left above the ghost's bottom line.
+        let row = &display.frame().rows()[8];
+        assert_eq!(
+            (row[1].fg_color, row[1].bg_color),
+            (Color::Black, Color::Gray)
+        );
+        assert_eq!(
+            (row[7].fg_color, row[7].bg_color),
+            (Color::Black, Color::DarkPurple)
+        );
+        assert_eq!(
+            (row[9].fg_color, row[9].bg_color),
+            (Color::DarkPurple, Color::DarkPurple)
+        );
+        // The sidebar keeps full-size cells, which are spaces on the piece's colour.
+        let next = &display.frame().rows()[11][BOARD_WIDTH as usize * 2 + 7];
+        assert_eq!((next.c, next.bg_color), (' ', Color::Yellow));
+    }
+
+    #[test]
+    fn small_cells_show_glyphs_at_full_size() {
+        let mut display = TextDisplay::new(FRAME_WIDTH, FRAME_HEIGHT);
+        let expected = render(&game(), &mut display);
+        display.frame_mut().cell_style = CellStyle::Small;
+        assert_eq!(render(&game(), &mut display), expected);
+    }
+
+    #[test]
+    fn terminal_too_small() {
+        let mut display = TextDisplay::new(FRAME_WIDTH, FRAME_HEIGHT);
+        display.resize((40, 12));
+        assert!(!display.fits());
+        let expected = text(&["Terminal too small", "Need 66x22, have 40x12"]);
+        assert_eq!(render(&game(), &mut display), expected);
+
+        display.resize((16, 30));
+        let expected = text(&["Terminal too sma", "Need 66x22, have"]);
+        assert_eq!(render(&game(), &mut display), expected);
+    }
+}
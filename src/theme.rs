@@ -0,0 +1,292 @@
+use crate::piece::*;
+use crate::util::Color;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// A theme as written in a theme file:
+///
+/// ```toml
+/// [colors]           # background, text, highlight, border and garbage
+/// text = "#800000"
+///
+/// [pieces]           # one colour per piece
+/// I = "#00d7d7"
+///
+/// [ghosts]           # the ghost piece's colour for each piece
+/// I = "#00af87"
+///
+/// [glyphs]           # optional: two characters drawn for a piece's cells instead of a solid block,
+/// I = "##"           # as well as for the ghost and garbage cells
+/// ghost = ".."
+/// ```
+///
+/// Colours that aren't given are left to the terminal's default foreground and background.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    #[serde(default)]
+    colors: HashMap<String, String>,
+    #[serde(default)]
+    pieces: HashMap<String, String>,
+    #[serde(default)]
+    ghosts: HashMap<String, String>,
+    #[serde(default)]
+    glyphs: HashMap<String, String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+#[derive(Clone)]
+pub struct Theme {
+    colors: HashMap<Color, Rgb>,
+    glyphs: HashMap<Color, String>,
+}
+
+#[derive(Debug)]
+pub enum ThemeError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ThemeError::Io(e) => write!(f, "could not read theme: {}", e),
+            ThemeError::Parse(e) => write!(f, "could not parse theme: {}", e),
+            ThemeError::Invalid(e) => write!(f, "invalid theme: {}", e),
+        }
+    }
+}
+
+/// Themes that ship with the game, available by name.
+pub const BUILTIN: [(&str, &str); 3] = [
+    ("classic", include_str!("../themes/classic.toml")),
+    ("vivid", include_str!("../themes/vivid.toml")),
+    ("mono", include_str!("../themes/mono.toml")),
+];
+
+/// Loads a theme from a file, or from the built-in themes if no such file exists.
+pub fn load(name: &str) -> Result<Theme, ThemeError> {
+    if let Some((_, text)) = BUILTIN.iter().find(|(n, _)| *n == name) {
+        if !Path::new(name).exists() {
+            return parse(text);
+        }
+    }
+    let text = std::fs::read_to_string(name).map_err(ThemeError::Io)?;
+    parse(&text)
+}
+
+pub fn parse(text: &str) -> Result<Theme, ThemeError> {
+    let file: ThemeFile = toml::from_str(text).map_err(ThemeError::Parse)?;
+    let mut theme = Theme {
+        colors: HashMap::new(),
+        glyphs: HashMap::new(),
+    };
+
+    for (key, value) in &file.colors {
+        let color = match key.as_str() {
+            "background" => Color::Black,
+            "text" => Color::Text,
+            "highlight" => Color::Highlight,
+            "border" => Color::Border,
+            "garbage" => Color::Gray,
+            _ => return Err(ThemeError::Invalid(format!("unknown colour {}", key))),
+        };
+        theme.colors.insert(color, parse_rgb(value)?);
+    }
+    for (key, value) in &file.pieces {
+        theme.colors.insert(piece(key)?.color, parse_rgb(value)?);
+    }
+    for (key, value) in &file.ghosts {
+        theme
+            .colors
+            .insert(piece(key)?.get_shadow_color(), parse_rgb(value)?);
+    }
+
+    for (key, glyph) in &file.glyphs {
+        if glyph.chars().count() != 2 || glyph.chars().any(char::is_control) {
+            return Err(ThemeError::Invalid(format!(
+                "glyph {:?} for {} is not two characters",
+                glyph, key
+            )));
+        }
+        let colors = match key.as_str() {
+            "ghost" => PieceKind::ALL
+                .iter()
+                .map(|&k| Piece::new(k).get_shadow_color())
+                .collect(),
+            "garbage" => vec![Color::Gray],
+            _ => vec![piece(key)?.color],
+        };
+        for color in colors {
+            theme.glyphs.insert(color, glyph.clone());
+        }
+    }
+    Ok(theme)
+}
+
+fn piece(name: &str) -> Result<Piece, ThemeError> {
+    PieceKind::ALL
+        .iter()
+        .find(|k| k.name() == name)
+        .map(|&k| Piece::new(k))
+        .ok_or_else(|| ThemeError::Invalid(format!("unknown piece {}", name)))
+}
+
+/// Parses a colour written as `#rrggbb`.
+fn parse_rgb(text: &str) -> Result<Rgb, ThemeError> {
+    let invalid = || ThemeError::Invalid(format!("{:?} is not a colour like #ff8000", text));
+    let hex = text.strip_prefix('#').ok_or_else(invalid)?;
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    Ok(Rgb(channel(0), channel(2), channel(4)))
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        parse(BUILTIN[0].1).unwrap()
+    }
+}
+
+impl Theme {
+    /// The built-in monochrome theme, which tells every kind of cell apart without colour.
+    pub fn monochrome() -> Theme {
+        parse(BUILTIN[2].1).unwrap()
+    }
+
+    /// The colour `color` is drawn in, or None for the terminal's default.
+    pub fn rgb(&self, color: Color) -> Option<Rgb> {
+        self.colors.get(&color).copied()
+    }
+
+    /// The two characters cells of `color` are drawn as, if the theme has a pattern for them.
+    pub fn glyph(&self, color: Color) -> Option<&str> {
+        self.glyphs.get(&color).map(String::as_str)
+    }
+
+    /// Whether any cells are drawn as glyphs rather than in their colour.
+    pub fn has_glyphs(&self) -> bool {
+        !self.glyphs.is_empty()
+    }
+}
+
+/// How many colours the terminal can show.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Depth {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+/// The standard colours of the 16 colour palette, as xterm shows them.
+const ANSI16: [Rgb; 16] = [
+    Rgb(0, 0, 0),
+    Rgb(205, 0, 0),
+    Rgb(0, 205, 0),
+    Rgb(205, 205, 0),
+    Rgb(0, 0, 238),
+    Rgb(205, 0, 205),
+    Rgb(0, 205, 205),
+    Rgb(229, 229, 229),
+    Rgb(127, 127, 127),
+    Rgb(255, 0, 0),
+    Rgb(0, 255, 0),
+    Rgb(255, 255, 0),
+    Rgb(92, 92, 255),
+    Rgb(255, 0, 255),
+    Rgb(0, 255, 255),
+    Rgb(255, 255, 255),
+];
+
+/// The levels of each channel in the 6x6x6 colour cube of the 256 colour palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+impl Depth {
+    /// Guesses the terminal's colour support from `COLORTERM` and `TERM`.
+    pub fn detect() -> Depth {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            Depth::TrueColor
+        } else if term.contains("256color") {
+            Depth::Ansi256
+        } else {
+            Depth::Ansi16
+        }
+    }
+
+    /// The closest colour to `rgb` the terminal can show, or its default colour if `rgb` is None.
+    pub fn convert(self, rgb: Option<Rgb>) -> TermColor {
+        match (self, rgb) {
+            (_, None) => TermColor::Default,
+            (Depth::TrueColor, Some(rgb)) => TermColor::Rgb(rgb),
+            (Depth::Ansi256, Some(rgb)) => TermColor::Ansi256(ansi256(rgb)),
+            (Depth::Ansi16, Some(rgb)) => TermColor::Ansi16(ansi16(rgb)),
+        }
+    }
+}
+
+/// A colour as the terminal is told to show it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TermColor {
+    Default,
+    Rgb(Rgb),
+    /// An index into the 256 colour palette.
+    Ansi256(u8),
+    /// An index into the 16 colour palette.
+    Ansi16(u8),
+}
+
+impl TermColor {
+    /// The SGR parameters that set the foreground, or the background, to this colour.
+    pub fn sgr(self, background: bool) -> String {
+        let base = if background { 40 } else { 30 };
+        match self {
+            TermColor::Default => (base + 9).to_string(),
+            TermColor::Rgb(rgb) => format!("{};2;{};{};{}", base + 8, rgb.0, rgb.1, rgb.2),
+            TermColor::Ansi256(i) => format!("{};5;{}", base + 8, i),
+            TermColor::Ansi16(i) if i < 8 => (base + i).to_string(),
+            TermColor::Ansi16(i) => (base + 60 + i - 8).to_string(),
+        }
+    }
+}
+
+fn distance(a: Rgb, b: Rgb) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+/// The closest colour of the 256 colour palette's colour cube and grey ramp.
+fn ansi256(rgb: Rgb) -> u8 {
+    let level = |c: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&i| (CUBE_LEVELS[i] as i32 - c as i32).abs())
+            .unwrap()
+    };
+    let (r, g, b) = (level(rgb.0), level(rgb.1), level(rgb.2));
+    let cube = Rgb(CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]);
+
+    let average = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let grey_index = (average.saturating_sub(3) / 10).min(23) as u8;
+    let grey_level = 8 + 10 * grey_index;
+    let grey = Rgb(grey_level, grey_level, grey_level);
+
+    if distance(rgb, grey) < distance(rgb, cube) {
+        232 + grey_index
+    } else {
+        16 + 36 * r as u8 + 6 * g as u8 + b as u8
+    }
+}
+
+/// The index of the closest colour of the 16 colour palette.
+fn ansi16(rgb: Rgb) -> u8 {
+    (0..ANSI16.len())
+        .min_by_key(|&i| distance(rgb, ANSI16[i]))
+        .unwrap() as u8
+}
@@ -0,0 +1,178 @@
+use crate::ai::*;
+use crate::game::{Game, Mode};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use std::path::PathBuf;
+use std::thread;
+
+pub struct TuneConfig {
+    /// Number of weight sets in each generation.
+    pub population: usize,
+    pub generations: u32,
+    /// Number of games every weight set plays per generation.
+    pub games: u32,
+    /// Games are cut off after this many pieces so good weight sets don't play forever.
+    pub max_pieces: u32,
+    pub threads: usize,
+    pub seed: u64,
+    pub out: PathBuf,
+}
+
+struct Candidate {
+    weights: Weights,
+    fitness: f64,
+}
+
+/// Evolves the bot's evaluation weights with a genetic algorithm. Every generation plays the same set of
+/// seeded headless games, so candidates are compared on identical piece sequences. Tuning picks up from
+/// any weights already in `config.out`, and the best weights found so far are written back there after
+/// each generation.
+pub fn run(config: &TuneConfig) {
+    let mut rng = Pcg32::seed_from_u64(config.seed);
+
+    let mut population: Vec<Weights> = Vec::with_capacity(config.population);
+    population.push(Weights::load(&config.out).unwrap_or_default());
+    while population.len() < config.population {
+        population.push(random_weights(&mut rng));
+    }
+
+    let mut best: Option<Candidate> = None;
+
+    for generation in 0..config.generations {
+        let seeds: Vec<u64> = (0..config.games).map(|_| rng.gen()).collect();
+        let mut candidates = evaluate_population(&population, &seeds, config);
+        candidates.sort_by(|a, b| b.fitness.partial_cmp(&a.fitness).unwrap());
+
+        let mean = candidates.iter().map(|c| c.fitness).sum::<f64>() / candidates.len() as f64;
+        println!(
+            "generation {}: best {:.1} lines, mean {:.1} lines",
+            generation + 1,
+            candidates[0].fitness,
+            mean
+        );
+
+        if best
+            .as_ref()
+            .is_none_or(|b| candidates[0].fitness > b.fitness)
+        {
+            best = Some(Candidate {
+                weights: candidates[0].weights,
+                fitness: candidates[0].fitness,
+            });
+            if let Err(e) = candidates[0].weights.save(&config.out) {
+                eprintln!("could not write weights to {}: {}", config.out.display(), e);
+            }
+        }
+
+        population = next_generation(&candidates, &mut rng);
+    }
+
+    if let Some(b) = best {
+        println!("best weights ({:.1} lines): {:?}", b.fitness, b.weights);
+        println!("written to {}", config.out.display());
+    }
+}
+
+/// Plays every seed with every weight set, spreading the weight sets over `config.threads` threads.
+/// Fitness is the average number of lines cleared.
+fn evaluate_population(
+    population: &[Weights],
+    seeds: &[u64],
+    config: &TuneConfig,
+) -> Vec<Candidate> {
+    let chunk_size = population.len().div_ceil(config.threads);
+    let mut candidates = Vec::with_capacity(population.len());
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = population
+            .chunks(chunk_size.max(1))
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|&weights| Candidate {
+                            weights,
+                            fitness: fitness(weights, seeds, config.max_pieces),
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            candidates.extend(handle.join().unwrap());
+        }
+    });
+
+    candidates
+}
+
+fn fitness(weights: Weights, seeds: &[u64], max_pieces: u32) -> f64 {
+    let bot = Bot::new(weights);
+    let mut lines = 0;
+    for &seed in seeds {
+        let mut game = Game::with_seed(Mode::Marathon, seed);
+        bot.autoplay(&mut game, max_pieces);
+        lines += game.lines;
+    }
+    lines as f64 / seeds.len() as f64
+}
+
+/// Builds the next generation: the best tenth survives unchanged, the rest are offspring of tournament
+/// winners.
+fn next_generation(ranked: &[Candidate], rng: &mut Pcg32) -> Vec<Weights> {
+    let elite = (ranked.len() / 10).max(1);
+    let mut population: Vec<Weights> = ranked[..elite].iter().map(|c| c.weights).collect();
+
+    while population.len() < ranked.len() {
+        let a = tournament(ranked, rng);
+        let b = tournament(ranked, rng);
+        let mut child = crossover(a, b);
+        if rng.gen_bool(0.2) {
+            let i = rng.gen_range(0, WEIGHT_COUNT);
+            child[i] += rng.gen_range(-0.2, 0.2);
+        }
+        population.push(Weights::from_array(normalize(child)));
+    }
+
+    population
+}
+
+/// Picks the fittest of a few random candidates.
+fn tournament<'a>(ranked: &'a [Candidate], rng: &mut Pcg32) -> &'a Candidate {
+    (0..3)
+        .map(|_| &ranked[rng.gen_range(0, ranked.len())])
+        .max_by(|a, b| a.fitness.partial_cmp(&b.fitness).unwrap())
+        .unwrap()
+}
+
+/// Averages two parents, leaning towards the fitter one.
+fn crossover(a: &Candidate, b: &Candidate) -> [f64; WEIGHT_COUNT] {
+    let total = a.fitness + b.fitness;
+    let wa = if total > 0.0 { a.fitness / total } else { 0.5 };
+    let (xa, xb) = (a.weights.to_array(), b.weights.to_array());
+    let mut child = [0.0; WEIGHT_COUNT];
+    for i in 0..WEIGHT_COUNT {
+        child[i] = xa[i] * wa + xb[i] * (1.0 - wa);
+    }
+    child
+}
+
+fn random_weights(rng: &mut Pcg32) -> Weights {
+    let mut w = [0.0; WEIGHT_COUNT];
+    for x in w.iter_mut() {
+        *x = rng.gen_range(-1.0, 1.0);
+    }
+    Weights::from_array(normalize(w))
+}
+
+/// Evaluations only compare placements against each other, so weights are kept at unit length.
+fn normalize(mut w: [f64; WEIGHT_COUNT]) -> [f64; WEIGHT_COUNT] {
+    let len = w.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if len > 0.0 {
+        for x in w.iter_mut() {
+            *x /= len;
+        }
+    }
+    w
+}
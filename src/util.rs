@@ -1,25 +1,60 @@
-#[derive(Debug, PartialEq, Copy, Clone)]
-//https://jonasjacek.github.io/colors/
+use serde::{Deserialize, Serialize};
+
+/// What a cell is drawn as. The actual colours come from the theme (see `theme.rs`).
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
 pub enum Color {
-    Black = 0,
-    Cyan = 44,
-    Purple = 90,
-    Green = 22,
-    Red = 1,
-    Blue = 21,
-    Orange = 202,
-    Yellow = 190,
-    DarkCyan = 36,
-    DarkPurple = 5,
-    DarkGreen = 2,
-    DarkRed = 9,
-    DarkBlue = 4,
-    DarkOrange = 94,
-    DarkYellow = 11,
+    Black,
+    Cyan,
+    Purple,
+    Green,
+    Red,
+    Blue,
+    Orange,
+    Yellow,
+    DarkCyan,
+    DarkPurple,
+    DarkGreen,
+    DarkRed,
+    DarkBlue,
+    DarkOrange,
+    DarkYellow,
+    Gray,
+    Text,
+    /// Text that needs the player's attention, like finesse corrections.
+    Highlight,
+    Border,
+}
+
+impl Color {
+    /// The darker colour ghost pieces of this colour are drawn in.
+    pub fn shadow(self) -> Color {
+        match self {
+            Color::Cyan => Color::DarkCyan,
+            Color::Purple => Color::DarkPurple,
+            Color::Green => Color::DarkGreen,
+            Color::Red => Color::DarkRed,
+            Color::Blue => Color::DarkBlue,
+            Color::Orange => Color::DarkOrange,
+            Color::Yellow => Color::DarkYellow,
+            _ => Color::Black,
+        }
+    }
 }
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum Direction {
     Left,
     Right,
+    /// A half turn, 180 degrees.
+    Half,
+}
+
+/// Reads only the `version` field of a JSON file, so that files from other versions get a clear error rather
+/// than whatever field they happen to disagree on.
+pub fn json_version(text: &str) -> serde_json::Result<u32> {
+    #[derive(Deserialize)]
+    struct Header {
+        version: u32,
+    }
+    serde_json::from_str::<Header>(text).map(|header| header.version)
 }